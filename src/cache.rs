@@ -0,0 +1,236 @@
+//! Remembering DeArrow's answers for a while, since popular videos get posted over and over.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::state::Sweep;
+use crate::{BrandingResponse, ImageFormat, Thumbnail};
+
+/// Values that expire `ttl` after they were stored. Holds at most `capacity` of them,
+/// evicting the oldest when full.
+struct Timed<K, V> {
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Timed<K, V> {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            capacity,
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().ok()?;
+
+        entries
+            .get(key)
+            .filter(|(at, _)| at.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn insert(&self, key: K, value: V) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+
+            if entries.len() >= self.capacity
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, (at, _))| *at)
+                    .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    fn sweep(&self) -> usize {
+        let Ok(mut entries) = self.entries.lock() else {
+            return 0;
+        };
+
+        let before = entries.len();
+        entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+
+        before - entries.len()
+    }
+}
+
+/// Branding and downloaded thumbnails per video, so a repost doesn't hit the network at all.
+pub struct ResponseCache {
+    branding: Timed<String, BrandingResponse>,
+    /// Keyed by the thumbnail's timestamp too, as a video can have several.
+    thumbnails: Timed<(String, Option<u32>), Thumbnail>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            branding: Timed::new(ttl, capacity),
+            thumbnails: Timed::new(ttl, capacity),
+        }
+    }
+
+    pub fn branding(&self, vid_id: &str) -> Option<BrandingResponse> {
+        self.branding.get(&vid_id.to_string())
+    }
+
+    pub fn insert_branding(&self, vid_id: &str, branding: BrandingResponse) {
+        self.branding.insert(vid_id.to_string(), branding);
+    }
+
+    pub fn thumbnail(&self, vid_id: &str, timestamp: Option<f32>) -> Option<Thumbnail> {
+        self.thumbnails
+            .get(&(vid_id.to_string(), timestamp.map(f32::to_bits)))
+    }
+
+    pub fn insert_thumbnail(&self, vid_id: &str, timestamp: Option<f32>, thumbnail: Thumbnail) {
+        self.thumbnails
+            .insert((vid_id.to_string(), timestamp.map(f32::to_bits)), thumbnail);
+    }
+}
+
+impl Sweep for ResponseCache {
+    fn sweep(&self) -> usize {
+        self.branding.sweep() + self.thumbnails.sweep()
+    }
+}
+
+/// Downloaded thumbnails kept on disk, so they survive restarts. A file's mtime is bumped
+/// whenever it's used, which is what [`DiskThumbnails::cleanup`] goes by.
+pub struct DiskThumbnails {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+impl DiskThumbnails {
+    pub fn open(
+        dir: PathBuf,
+        max_bytes: Option<u64>,
+        max_age: Option<Duration>,
+    ) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            max_age,
+        })
+    }
+
+    fn path(&self, vid_id: &str, timestamp: Option<f32>) -> PathBuf {
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp.to_bits().to_string(),
+            None => "best".to_string(),
+        };
+
+        self.dir.join(format!("{vid_id}-{timestamp}.thumb"))
+    }
+
+    pub async fn get(&self, vid_id: &str, timestamp: Option<f32>) -> Option<Thumbnail> {
+        let path = self.path(vid_id, timestamp);
+
+        tokio::task::spawn_blocking(move || {
+            let bytes = std::fs::read(&path).ok()?;
+            let format = ImageFormat::sniff(&bytes)?;
+
+            if let Err(e) = File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|x| x.set_modified(SystemTime::now()))
+            {
+                log::debug!("could not mark {} as used: {e}", path.display());
+            }
+
+            Some(Thumbnail { bytes, format })
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    pub async fn insert(&self, vid_id: &str, timestamp: Option<f32>, thumbnail: &Thumbnail) {
+        let path = self.path(vid_id, timestamp);
+
+        if let Err(e) = tokio::fs::write(&path, &thumbnail.bytes).await {
+            log::warn!("could not cache thumbnail at {}: {e}", path.display());
+        }
+    }
+
+    /// Removes thumbnails older than the max age, then the least recently used ones until
+    /// the total fits the max size. Returns how many files and bytes were reclaimed.
+    fn cleanup(&self) -> std::io::Result<(usize, u64)> {
+        let mut files = Vec::new();
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if meta.is_file() {
+                files.push((entry.path(), meta.len(), meta.modified()?));
+            }
+        }
+
+        // least recently used first.
+        files.sort_by_key(|(_, _, used)| *used);
+
+        let mut total = files.iter().map(|(_, len, _)| len).sum::<u64>();
+        let mut reclaimed = (0, 0);
+
+        for (path, len, used) in files {
+            let expired = self
+                .max_age
+                .is_some_and(|max_age| used.elapsed().unwrap_or_default() > max_age);
+            let too_big = self.max_bytes.is_some_and(|max_bytes| total > max_bytes);
+
+            if !expired && !too_big {
+                continue;
+            }
+
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    total -= len;
+                    reclaimed.0 += 1;
+                    reclaimed.1 += len;
+                }
+                Err(e) => log::warn!("could not remove cached thumbnail {}: {e}", path.display()),
+            }
+        }
+
+        Ok(reclaimed)
+    }
+
+    /// Cleans up now, and then every `interval`.
+    pub fn spawn_cleanup(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+
+            loop {
+                interval.tick().await;
+
+                let cache = self.clone();
+                match tokio::task::spawn_blocking(move || cache.cleanup()).await {
+                    Ok(Ok((files, bytes))) => log::info!(
+                        "thumbnail cache cleanup reclaimed {bytes} bytes in {files} files."
+                    ),
+                    Ok(Err(e)) => log::warn!("thumbnail cache cleanup failed: {e}"),
+                    Err(e) => log::warn!("thumbnail cache cleanup panicked: {e}"),
+                }
+            }
+        });
+    }
+}
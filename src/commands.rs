@@ -0,0 +1,659 @@
+//! Slash commands.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse, GuildId,
+    Permissions, ResolvedValue,
+};
+
+use crate::error::DeArrowError;
+use crate::youtube::find_video_id;
+use crate::{Handler, decision, describe_thumbnail, permissions, youtube};
+
+/// The commands guilds can have turned off. The owner commands can't be.
+pub const TOGGLEABLE: &[&str] = &["dearrow", "dearrow-at", "dearrow-explain"];
+
+/// A `<guild id>:<command>` pair, as passed on the command line.
+#[derive(Clone, Debug)]
+pub struct GuildCommand {
+    pub guild_id: GuildId,
+    pub command: String,
+}
+
+impl FromStr for GuildCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((guild_id, command)) = s.split_once(':') else {
+            anyhow::bail!("expected <guild id>:<command>");
+        };
+
+        let command = command.trim().trim_start_matches('/').to_ascii_lowercase();
+
+        if !TOGGLEABLE.contains(&command.as_str()) {
+            anyhow::bail!(
+                "can't disable /{command}, only {}",
+                TOGGLEABLE
+                    .iter()
+                    .map(|x| format!("/{x}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        Ok(GuildCommand {
+            guild_id: guild_id.trim().parse::<std::num::NonZeroU64>()?.into(),
+            command,
+        })
+    }
+}
+
+pub fn all(handler: &Handler) -> Vec<CreateCommand> {
+    let mut commands = vec![
+        CreateCommand::new("dearrow")
+            .description("De-clickbait a video.")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "The YouTube link.")
+                    .required(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "public",
+                "Show the reply to everyone in the channel, not just you.",
+            )),
+        CreateCommand::new("dearrow-at")
+            .description("De-clickbait a video, showing the frame at a timestamp of your choice.")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "The YouTube link.")
+                    .required(true),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "timestamp",
+                    "Where in the video to take the frame from, e.g. 90, 1:30 or 1m30s.",
+                )
+                .required(true),
+            ),
+        CreateCommand::new("dearrow-explain")
+            .description("Explain why the bot would or wouldn't de-clickbait a link.")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "The YouTube link.")
+                    .required(true),
+            ),
+        CreateCommand::new("dearrow-optout")
+            .description("Stop the bot from de-clickbaiting links in your messages."),
+        CreateCommand::new("dearrow-optin")
+            .description("Let the bot de-clickbait links in your messages again."),
+    ];
+
+    // only the owners can use these. they're hidden from everyone but server admins, though
+    // those still can't use them unless they're owners too.
+    if !handler.owners.is_empty() {
+        commands.push(
+            CreateCommand::new("maintenance")
+                .description("Pause or resume auto-replies. Owners only.")
+                .default_member_permissions(Permissions::ADMINISTRATOR)
+                .add_option(CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "on",
+                    "Pause auto-replies until turned off.",
+                ))
+                .add_option(CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "off",
+                    "Resume auto-replies.",
+                ))
+                .add_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::SubCommand,
+                        "until",
+                        "Pause auto-replies for a while.",
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(
+                            CommandOptionType::Integer,
+                            "minutes",
+                            "How long to pause for.",
+                        )
+                        .min_int_value(1)
+                        .required(true),
+                    ),
+                ),
+        );
+
+        commands.push(
+            CreateCommand::new("loglevel")
+                .description("Change how much the bot logs, without a restart. Owners only.")
+                .default_member_permissions(Permissions::ADMINISTRATOR)
+                .add_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "level",
+                        "The level to log at. \"info\" is the default.",
+                    )
+                    .add_string_choice("error", "error")
+                    .add_string_choice("warn", "warn")
+                    .add_string_choice("info", "info")
+                    .add_string_choice("debug", "debug")
+                    .add_string_choice("trace", "trace")
+                    .required(true),
+                ),
+        );
+    }
+
+    commands
+}
+
+fn string_option<'a>(command: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    command
+        .data
+        .options()
+        .into_iter()
+        .find(|x| x.name == name)
+        .and_then(|x| match x.value {
+            ResolvedValue::String(x) => Some(x),
+            _ => None,
+        })
+}
+
+fn bool_option(command: &CommandInteraction, name: &str) -> Option<bool> {
+    command
+        .data
+        .options()
+        .into_iter()
+        .find(|x| x.name == name)
+        .and_then(|x| match x.value {
+            ResolvedValue::Boolean(x) => Some(x),
+            _ => None,
+        })
+}
+
+async fn respond_error(ctx: &Context, command: &CommandInteraction, error: &str) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(error)
+            .ephemeral(true),
+    );
+
+    if let Err(e) = command.create_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
+
+pub async fn handle(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    if command.data.name == "maintenance" {
+        maintenance(handler, ctx, command).await;
+        return;
+    }
+
+    if command.data.name == "loglevel" {
+        loglevel(handler, ctx, command).await;
+        return;
+    }
+
+    // the allowlists keep replies out of channels, commands shouldn't get around them.
+    if !handler.is_allowed(&ctx.cache, command.guild_id, command.channel_id) {
+        respond_error(ctx, command, "The bot is not enabled here.").await;
+        return;
+    }
+
+    // a user's own choice, it shouldn't have to wait on maintenance or the limits.
+    if let Some(opted_out) = match command.data.name.as_str() {
+        "dearrow-optout" => Some(true),
+        "dearrow-optin" => Some(false),
+        _ => None,
+    } {
+        opt_out(handler, ctx, command, opted_out).await;
+        return;
+    }
+
+    if let Some(guild_id) = command.guild_id
+        && handler
+            .disabled_commands
+            .contains(&(guild_id, command.data.name.clone()))
+    {
+        respond_error(ctx, command, "This command is disabled here.").await;
+        return;
+    }
+
+    if handler.maintenance.is_active() {
+        respond_error(
+            ctx,
+            command,
+            "The bot is under maintenance, try again later.",
+        )
+        .await;
+        return;
+    }
+
+    // every command so far calls out to DeArrow, so they all count against the limits.
+    let allowed = command
+        .guild_id
+        .is_none_or(|guild_id| handler.guild_command_limits.try_acquire(guild_id))
+        && handler.user_command_limits.try_acquire(command.user.id);
+
+    if !allowed {
+        log::info!(
+            "rate limited /{} from {} in {:?}.",
+            command.data.name,
+            command.user.id,
+            command.guild_id
+        );
+        respond_error(ctx, command, "Too many commands, try again later.").await;
+        return;
+    }
+
+    match command.data.name.as_str() {
+        "dearrow" => dearrow(handler, ctx, command).await,
+        "dearrow-at" => dearrow_at(handler, ctx, command).await,
+        "dearrow-explain" => dearrow_explain(handler, ctx, command).await,
+        name => log::warn!("received unknown command {name}"),
+    }
+}
+
+async fn dearrow(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    let Some(id) = string_option(command, "url").and_then(find_video_id) else {
+        respond_error(ctx, command, "That doesn't look like a YouTube link.").await;
+        return;
+    };
+
+    let deferred = if bool_option(command, "public").unwrap_or(false) {
+        command.defer(&ctx.http).await
+    } else {
+        command.defer_ephemeral(&ctx.http).await
+    };
+
+    if let Err(e) = deferred {
+        log::error!("could not defer command: {e:#?}");
+        return;
+    }
+
+    let response = match handler.branding(&id).await {
+        Err(DeArrowError::NotFound) => {
+            EditInteractionResponse::new().content("DeArrow has no data for this video.")
+        }
+        Err(e) => {
+            log::error!("failed to get branding! {e:#?}");
+            EditInteractionResponse::new().content("Couldn't reach DeArrow, try again later.")
+        }
+        Ok(branding) => match handler.policy.should_reply(decision::ReplyDecisionInputs {
+            branding: &branding,
+            forced: false,
+            forced_thumbnail: false,
+            embed_titles: &[],
+        }) {
+            decision::Decision::Skip(decision::Skip::NoTitle) => {
+                EditInteractionResponse::new().content("DeArrow has no titles for this video.")
+            }
+            decision::Decision::Skip(skip) => EditInteractionResponse::new()
+                .content(format!("Not de-clickbaiting this one: {skip}.")),
+            decision::Decision::Reply { title, thumbnail } => {
+                let thumb = match thumbnail {
+                    Ok(thumbnail) => {
+                        match handler.fetch_thumbnail(&id, thumbnail.timestamp).await {
+                            Ok(thumb) => Ok((thumb, describe_thumbnail(thumbnail))),
+                            Err(e) => {
+                                log::error!("failed to retrieve thumbnail: {e:#?}");
+                                Err("could not be generated".to_string())
+                            }
+                        }
+                    }
+                    Err(reason) => Err(reason.to_string()),
+                };
+
+                let mut response = match thumb {
+                    Ok((thumb, status)) => EditInteractionResponse::new()
+                        .embed(
+                            handler
+                                .build_embed(&id, &branding, title, &status, None)
+                                .attachment(thumb.filename(0)),
+                        )
+                        .new_attachment(thumb.into_attachment(
+                            0,
+                            handler.thumbnail_alt_text(&title.title).as_deref(),
+                        )),
+                    Err(status) => EditInteractionResponse::new()
+                        .embed(handler.build_embed(&id, &branding, title, &status, None)),
+                };
+
+                for button in handler.buttons(&id) {
+                    response = response.button(button);
+                }
+
+                response
+            }
+        },
+    };
+
+    if let Err(e) = command.edit_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
+
+async fn dearrow_at(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    let Some(id) = string_option(command, "url").and_then(find_video_id) else {
+        respond_error(ctx, command, "That doesn't look like a YouTube link.").await;
+        return;
+    };
+
+    let Some(timestamp) = string_option(command, "timestamp").and_then(youtube::parse_timestamp)
+    else {
+        respond_error(ctx, command, "That doesn't look like a timestamp.").await;
+        return;
+    };
+
+    if let Err(e) = command.defer(&ctx.http).await {
+        log::error!("could not defer command: {e:#?}");
+        return;
+    }
+
+    let response = match handler.branding(&id).await {
+        Err(DeArrowError::NotFound) => {
+            EditInteractionResponse::new().content("DeArrow has no data for this video.")
+        }
+        Err(e) => {
+            log::error!("failed to get branding! {e:#?}");
+            EditInteractionResponse::new().content("Couldn't reach DeArrow, try again later.")
+        }
+        Ok(branding) => match handler.policy.choose_title(&branding, false).ok() {
+            None => EditInteractionResponse::new().content("DeArrow has no titles for this video."),
+            Some(_)
+                if branding
+                    .duration()
+                    .is_some_and(|duration| timestamp > duration) =>
+            {
+                EditInteractionResponse::new().content(format!(
+                    "The video is only {}s long.",
+                    branding.duration().unwrap_or_default()
+                ))
+            }
+            // frames at whatever time someone asks for are unlikely to be asked for again,
+            // they'd only push the thumbnails worth keeping out of the caches.
+            Some(title) => match handler.download_thumbnail(&id, Some(timestamp)).await {
+                Ok(thumb) => {
+                    let embed = handler
+                        .build_embed(
+                            &id,
+                            &branding,
+                            title,
+                            &format!("frame at {timestamp}s"),
+                            None,
+                        )
+                        .attachment(thumb.filename(0));
+
+                    EditInteractionResponse::new().embed(embed).new_attachment(
+                        thumb.into_attachment(
+                            0,
+                            handler.thumbnail_alt_text(&title.title).as_deref(),
+                        ),
+                    )
+                }
+                Err(e) => {
+                    log::error!("failed to retrieve thumbnail: {e:#?}");
+                    EditInteractionResponse::new().embed(handler.build_embed(
+                        &id,
+                        &branding,
+                        title,
+                        "could not be generated",
+                        None,
+                    ))
+                }
+            },
+        },
+    };
+
+    if let Err(e) = command.edit_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
+
+async fn dearrow_explain(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    let url = string_option(command, "url").unwrap_or_default();
+
+    if let Err(e) = command.defer_ephemeral(&ctx.http).await {
+        log::error!("could not defer command: {e:#?}");
+        return;
+    }
+
+    let mut lines = Vec::new();
+
+    let id = match (find_video_id(url), &handler.clips, youtube::find_clip(url)) {
+        (Some(id), _, _) => Some(id),
+        (None, Some(clips), Some(clip_id)) => match clips.resolve(clip_id).await {
+            Ok(id) => {
+                lines.push(format!("✅ Resolved clip `{clip_id}` to its video."));
+                Some(id)
+            }
+            Err(e) => {
+                lines.push(format!("❌ Couldn't resolve clip `{clip_id}`: {e}"));
+                None
+            }
+        },
+        (None, None, Some(_)) => {
+            lines.push("❌ Clip links are not enabled on this bot.".to_string());
+            None
+        }
+        (None, _, None) => {
+            lines.push("❌ Not a link to a YouTube video.".to_string());
+            None
+        }
+    };
+
+    if let Some(ref id) = id {
+        lines.push(format!("✅ Found video `{id}`."));
+
+        // the same gates, in the same order, as for a link posted here by whoever asks.
+        let gate = handler
+            .message_gate(
+                &ctx.cache,
+                command.guild_id,
+                command.channel_id,
+                command.user.id,
+                false,
+            )
+            .and_then(|()| handler.video_gate(command.channel_id, id, false));
+
+        match gate {
+            Err(gate) => lines.push(format!("❌ Not de-clickbaited here: {gate}.")),
+            Ok(()) => explain_video(handler, id, &mut lines).await,
+        }
+
+        let can_embed = command.guild_id.is_none_or(|guild_id| {
+            permissions::in_channel(&ctx.cache, guild_id, command.channel_id)
+                .is_none_or(|x| x.contains(Permissions::EMBED_LINKS))
+        });
+
+        if !can_embed {
+            lines.push("⚠️ The bot can't embed links in this channel.".to_string());
+        }
+    }
+
+    let response = EditInteractionResponse::new().content(lines.join("\n"));
+
+    if let Err(e) = command.edit_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
+
+/// Explains whether and how `id` would be de-clickbaited, past the gates that don't depend on
+/// the video itself.
+async fn explain_video(handler: &Handler, id: &str, lines: &mut Vec<String>) {
+    let branding = match handler.branding(id).await {
+        Ok(branding) => branding,
+        Err(e) => {
+            lines.push(format!("❌ Couldn't get branding from DeArrow: {e}"));
+            return;
+        }
+    };
+
+    // discord's embed of a link shows the title on YouTube.
+    let embed_titles = if handler.policy.skip_matching_embeds {
+        match youtube::oembed(&handler.client, id).await {
+            Ok(oembed) => vec![oembed.title],
+            Err(e) => {
+                lines.push(format!(
+                    "➖ Couldn't look up the original title to compare with: {e}"
+                ));
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let (title, thumbnail) = match handler.policy.should_reply(decision::ReplyDecisionInputs {
+        branding: &branding,
+        forced: false,
+        forced_thumbnail: false,
+        embed_titles: &embed_titles,
+    }) {
+        decision::Decision::Reply { title, thumbnail } => (title, thumbnail),
+        decision::Decision::Skip(skip) => {
+            lines.push(format!("❌ Not de-clickbaited: {skip}."));
+            return;
+        }
+    };
+
+    if !handler.video_exists(id).await {
+        lines.push(
+            "❌ Not de-clickbaited: the video is no longer available on YouTube.".to_string(),
+        );
+        return;
+    }
+
+    lines.push(format!(
+        "✅ Title: **{}** ({}, {} votes, {}).",
+        title.title,
+        handler.trust_tiers.tier(title.votes, title.locked),
+        title.votes,
+        if title.locked { "locked" } else { "not locked" }
+    ));
+
+    match thumbnail {
+        Ok(thumbnail) => match handler.fetch_thumbnail(id, thumbnail.timestamp).await {
+            Ok(_) => lines.push(format!("✅ Thumbnail: {}.", describe_thumbnail(thumbnail))),
+            Err(e) => lines.push(format!("➖ No thumbnail, downloading it failed: {e}")),
+        },
+        Err(reason) => lines.push(format!("➖ No thumbnail: {reason}.")),
+    }
+}
+
+async fn maintenance(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    if !handler.owners.contains(&command.user.id) {
+        respond_error(ctx, command, "Only the bot's owners can do that.").await;
+        return;
+    }
+
+    let Some(subcommand) = command.data.options().into_iter().next() else {
+        return;
+    };
+
+    let content = match (subcommand.name, subcommand.value) {
+        ("on", _) => {
+            handler.maintenance.start(None);
+            "Auto-replies are paused until maintenance is turned off.".to_string()
+        }
+        ("off", _) => {
+            handler.maintenance.end();
+            "Auto-replies are back on.".to_string()
+        }
+        ("until", ResolvedValue::SubCommand(options)) => {
+            let minutes = options
+                .iter()
+                .find_map(|x| match x.value {
+                    ResolvedValue::Integer(x) if x > 0 => Some(x as u64),
+                    _ => None,
+                })
+                .unwrap_or(1);
+
+            handler
+                .maintenance
+                .start(Some(Instant::now() + Duration::from_secs(minutes * 60)));
+            format!("Auto-replies are paused for {minutes} minutes.")
+        }
+        (name, _) => {
+            log::warn!("received unknown maintenance subcommand {name}");
+            return;
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+
+    if let Err(e) = command.create_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
+
+async fn loglevel(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    if !handler.owners.contains(&command.user.id) {
+        respond_error(ctx, command, "Only the bot's owners can do that.").await;
+        return;
+    }
+
+    let Some(level) =
+        string_option(command, "level").and_then(|x| x.parse::<log::LevelFilter>().ok())
+    else {
+        respond_error(ctx, command, "That's not a log level.").await;
+        return;
+    };
+
+    let previous = log::max_level();
+    log::set_max_level(level);
+    log::warn!(
+        "{} changed the log level from {previous} to {level}.",
+        command.user.id
+    );
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!("Logging at {level} now, was {previous}."))
+            .ephemeral(true),
+    );
+
+    if let Err(e) = command.create_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
+
+async fn opt_out(handler: &Handler, ctx: &Context, command: &CommandInteraction, opted_out: bool) {
+    let content = match handler.opt_outs.set(command.user.id, opted_out) {
+        Ok(changed) => {
+            if changed {
+                log::info!(
+                    "{} opted {} of de-clickbaiting.",
+                    command.user.id,
+                    if opted_out { "out" } else { "back in" }
+                );
+            }
+
+            match (opted_out, changed) {
+                (true, true) => "Your messages won't be de-clickbaited anymore.",
+                (true, false) => "Your messages already aren't de-clickbaited.",
+                (false, true) => "Your messages will be de-clickbaited again.",
+                (false, false) => "Your messages already are de-clickbaited.",
+            }
+        }
+        Err(e) => {
+            log::error!("could not save the opt-out of {}: {e:#?}", command.user.id);
+            "Couldn't save that, it may not last past a restart."
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+
+    if let Err(e) = command.create_response(&ctx.http, response).await {
+        log::error!("could not respond to command: {e:#?}");
+    }
+}
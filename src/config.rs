@@ -0,0 +1,84 @@
+//! Reading the settings from a TOML file passed with `--config`, as an alternative to a long
+//! list of flags or env vars.
+//!
+//! The file's values become the defaults of the matching args, so anything given on the
+//! command line or in the environment still wins.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{CommandFactory, FromArgMatches};
+
+use crate::Args;
+
+/// Parses the args, taking whatever they leave unset from the `--config` file, if any.
+pub fn parse_args() -> anyhow::Result<Args> {
+    // only to find the file, the rest may well be missing until it's read.
+    let config = Args::command()
+        .ignore_errors(true)
+        .get_matches()
+        .get_one::<PathBuf>("config")
+        .cloned();
+
+    let mut command = Args::command();
+
+    if let Some(path) = config {
+        for (id, value) in read(&path)? {
+            command = command.mut_arg(id, |arg| arg.default_value(value).required(false));
+        }
+    }
+
+    Ok(Args::from_arg_matches(&command.get_matches())?)
+}
+
+/// The settings in the file at `path`, as arg IDs and the values to parse for them.
+fn read(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read config file {}", path.display()))?;
+
+    let table = text
+        .parse::<toml::Table>()
+        .with_context(|| format!("config file {} is malformed", path.display()))?;
+
+    let command = Args::command();
+    let mut settings = Vec::new();
+
+    for (key, value) in table {
+        // the file may spell the settings like their flags, too.
+        let id = key.replace('-', "_");
+
+        if id == "config" || !command.get_arguments().any(|x| x.get_id() == id.as_str()) {
+            anyhow::bail!("config file {} has unknown setting {key:?}", path.display());
+        }
+
+        let value = match value {
+            toml::Value::Array(values) => values
+                .into_iter()
+                .map(scalar)
+                .collect::<Option<Vec<_>>>()
+                .map(|x| x.join(",")),
+            value => scalar(value),
+        };
+
+        let Some(value) = value else {
+            anyhow::bail!(
+                "config file {} has a value for {key:?} that isn't a string, number, bool or a list of those",
+                path.display()
+            );
+        };
+
+        settings.push((id, value));
+    }
+
+    Ok(settings)
+}
+
+fn scalar(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(x) => Some(x),
+        toml::Value::Integer(x) => Some(x.to_string()),
+        toml::Value::Float(x) => Some(x.to_string()),
+        toml::Value::Boolean(x) => Some(x.to_string()),
+        _ => None,
+    }
+}
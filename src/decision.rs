@@ -0,0 +1,980 @@
+//! The gates deciding whether, and with what, a video gets de-clickbaited.
+
+use crate::{
+    BrandingResponse, BrandingThumbnail, BrandingTitle, ReviewedTitle, ThumbnailMode, TitleMatch,
+    TitleSelection, UnknownDuration,
+};
+
+/// Why a video doesn't get a reply.
+#[derive(Debug)]
+pub enum Skip {
+    TooShort {
+        duration: f32,
+        min: f32,
+    },
+    UnknownDuration,
+    NoTitle,
+    UntrustedTitle {
+        votes: isize,
+        locked: bool,
+        min: isize,
+    },
+    TitleNotLocked,
+    /// The community locked in the original title, so there's nothing to de-clickbait.
+    ReviewedFine,
+    /// The title is the original one, at most with its capitalization fixed.
+    RecapitalizationOnly,
+    /// Discord's embed of the link already shows the title we'd reply with.
+    MatchesEmbed,
+}
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Skip::TooShort { duration, min } => {
+                write!(f, "video is shorter than {min}s ({duration}s)")
+            }
+            Skip::UnknownDuration => write!(f, "video duration is unknown"),
+            Skip::NoTitle => write!(f, "no titles were submitted"),
+            Skip::UntrustedTitle { votes, locked, min } => write!(
+                f,
+                "untrusted title (locked: {locked}, votes: {votes}, needs {min})"
+            ),
+            Skip::TitleNotLocked => write!(f, "only locked titles are allowed"),
+            Skip::ReviewedFine => write!(f, "the original title was reviewed and is fine"),
+            Skip::RecapitalizationOnly => write!(f, "the title only recapitalizes the original"),
+            Skip::MatchesEmbed => {
+                write!(f, "the original embed already shows the de-arrowed title")
+            }
+        }
+    }
+}
+
+/// Why a reply comes without a thumbnail.
+#[derive(Debug)]
+pub enum NoThumbnail {
+    Disabled,
+    NoneSubmitted,
+    Untrusted {
+        votes: isize,
+        locked: bool,
+        min: isize,
+    },
+    NotLocked,
+}
+
+impl std::fmt::Display for NoThumbnail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoThumbnail::Disabled => write!(f, "thumbnails are disabled"),
+            NoThumbnail::NoneSubmitted => write!(f, "no thumbnails were submitted"),
+            NoThumbnail::Untrusted { votes, locked, min } => write!(
+                f,
+                "untrusted thumbnail (locked: {locked}, votes: {votes}, needs {min})"
+            ),
+            NoThumbnail::NotLocked => write!(f, "only locked thumbnails are allowed"),
+        }
+    }
+}
+
+/// The thumbnail with the most votes, the earlier one on ties.
+fn most_voted<'a>(
+    thumbnails: impl Iterator<Item = &'a BrandingThumbnail>,
+) -> Option<&'a BrandingThumbnail> {
+    thumbnails.fold(None, |best, x| match best {
+        Some(best) if best.votes >= x.votes => Some(best),
+        _ => Some(x),
+    })
+}
+
+/// What a reply to one video is decided on.
+pub struct ReplyDecisionInputs<'a, 'e> {
+    pub branding: &'a BrandingResponse,
+    /// Someone asked for this reply explicitly, so the title gates don't apply.
+    pub forced: bool,
+    /// Someone asked for the thumbnail explicitly, so its gates don't apply.
+    pub forced_thumbnail: bool,
+    /// The titles of Discord's embeds of the message. Only looked at with
+    /// `--skip-matching-embeds`.
+    pub embed_titles: &'e [String],
+}
+
+pub enum Decision<'a> {
+    Reply {
+        title: &'a BrandingTitle,
+        /// A reply goes out with or without a thumbnail.
+        thumbnail: Result<&'a BrandingThumbnail, NoThumbnail>,
+    },
+    Skip(Skip),
+}
+
+/// The settings the gates go by, all from the command line.
+pub struct Policy {
+    pub thumbnail_mode: ThumbnailMode,
+    pub skip_matching_embeds: bool,
+    pub min_video_duration: Option<f32>,
+    pub unknown_duration: UnknownDuration,
+    pub title_selection: TitleSelection,
+    pub title_match: TitleMatch,
+    pub locked_only: bool,
+    /// How many votes unlocked titles and thumbnails need before we show them.
+    pub min_title_votes: isize,
+    pub min_thumbnail_votes: isize,
+    pub prefer_timestamped_thumbnails: bool,
+    /// Show the frame at DeArrow's random time when no submitted thumbnail is good enough.
+    pub random_frame_fallback: bool,
+    pub prefer_custom_titles: bool,
+    pub reviewed_title: ReviewedTitle,
+    pub skip_recapitalization_only: bool,
+}
+
+impl Policy {
+    /// Runs a video through all the gates, deciding whether it gets a reply and with what.
+    pub fn should_reply<'a>(&self, inputs: ReplyDecisionInputs<'a, '_>) -> Decision<'a> {
+        let title = match self.choose_title(inputs.branding, inputs.forced) {
+            Ok(title) => title,
+            Err(skip) => return Decision::Skip(skip),
+        };
+
+        if self.skip_matching_embeds
+            && !inputs.forced
+            && inputs
+                .embed_titles
+                .iter()
+                .any(|x| self.title_match.matches(x, &title.title))
+        {
+            return Decision::Skip(Skip::MatchesEmbed);
+        }
+
+        Decision::Reply {
+            title,
+            thumbnail: self.choose_thumbnail(inputs.branding, inputs.forced_thumbnail),
+        }
+    }
+
+    /// Picks the title to show, unless the video shouldn't be replied to at all. `forced`
+    /// replies skip the gates.
+    pub fn choose_title<'a>(
+        &self,
+        branding: &'a BrandingResponse,
+        forced: bool,
+    ) -> Result<&'a BrandingTitle, Skip> {
+        if let Some(min) = self.min_video_duration
+            && !forced
+        {
+            match branding.duration() {
+                Some(duration) if duration < min => return Err(Skip::TooShort { duration, min }),
+                None if self.unknown_duration == UnknownDuration::Skip => {
+                    return Err(Skip::UnknownDuration);
+                }
+                _ => {}
+            }
+        }
+
+        let title = branding
+            .select_title(self.title_selection)
+            .ok_or(Skip::NoTitle)?;
+
+        if forced {
+            return Ok(title);
+        }
+
+        // a rewrite says more than a recapitalization, unless only the latter is locked in.
+        if self.prefer_custom_titles
+            && title.original
+            && let Some(custom) = branding
+                .visible_titles()
+                .filter(|x| !x.original)
+                .find(|x| self.trust_title(x).is_ok())
+            && (custom.locked || !title.locked)
+        {
+            return Ok(custom);
+        }
+
+        self.trust_title(title)?;
+
+        if title.original && title.locked && self.reviewed_title != ReviewedTitle::Reply {
+            return Err(Skip::ReviewedFine);
+        }
+
+        if title.original && self.skip_recapitalization_only {
+            return Err(Skip::RecapitalizationOnly);
+        }
+
+        Ok(title)
+    }
+
+    /// Whether `title` is trusted enough to be shown.
+    fn trust_title(&self, title: &BrandingTitle) -> Result<(), Skip> {
+        if self.locked_only && !title.locked {
+            return Err(Skip::TitleNotLocked);
+        }
+
+        if !title.locked && title.votes < self.min_title_votes {
+            return Err(Skip::UntrustedTitle {
+                votes: title.votes,
+                locked: title.locked,
+                min: self.min_title_votes,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Picks the thumbnail to show. `forced` thumbnails skip the gates.
+    pub fn choose_thumbnail<'a>(
+        &self,
+        branding: &'a BrandingResponse,
+        forced: bool,
+    ) -> Result<&'a BrandingThumbnail, NoThumbnail> {
+        if self.thumbnail_mode == ThumbnailMode::Disabled && !forced {
+            return Err(NoThumbnail::Disabled);
+        }
+
+        let random_frame = branding
+            .random_frame
+            .as_ref()
+            .filter(|_| self.random_frame_fallback);
+
+        if forced {
+            return most_voted(branding.visible_thumbnails())
+                .or(random_frame)
+                .ok_or(NoThumbnail::NoneSubmitted);
+        }
+
+        let trusted = || {
+            branding
+                .visible_thumbnails()
+                .filter(|x| self.trust_thumbnail(x).is_ok())
+        };
+
+        if self.prefer_timestamped_thumbnails
+            && let Some(timestamped) = most_voted(trusted().filter(|x| x.timestamp.is_some()))
+        {
+            return Ok(timestamped);
+        }
+
+        if let Some(thumbnail) = most_voted(trusted()) {
+            return Ok(thumbnail);
+        }
+
+        // with only locked thumbnails allowed, a random frame isn't one either.
+        if !self.locked_only
+            && self.thumbnail_mode != ThumbnailMode::OnlyLocked
+            && let Some(frame) = random_frame
+        {
+            return Ok(frame);
+        }
+
+        // none are trusted, say why the best one isn't.
+        let thumbnail = branding.thumbnail().ok_or(NoThumbnail::NoneSubmitted)?;
+        self.trust_thumbnail(thumbnail).map(|_| thumbnail)
+    }
+
+    /// Whether `thumbnail` is trusted enough to be shown.
+    fn trust_thumbnail(&self, thumbnail: &BrandingThumbnail) -> Result<(), NoThumbnail> {
+        if !thumbnail.locked && thumbnail.votes < self.min_thumbnail_votes {
+            Err(NoThumbnail::Untrusted {
+                votes: thumbnail.votes,
+                locked: thumbnail.locked,
+                min: self.min_thumbnail_votes,
+            })
+        } else if !thumbnail.locked
+            && (self.locked_only || self.thumbnail_mode == ThumbnailMode::OnlyLocked)
+        {
+            Err(NoThumbnail::NotLocked)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(skip_recapitalization_only: bool) -> Policy {
+        Policy {
+            thumbnail_mode: ThumbnailMode::Enabled,
+            skip_matching_embeds: false,
+            min_video_duration: None,
+            unknown_duration: UnknownDuration::Process,
+            title_selection: TitleSelection::First,
+            title_match: TitleMatch::Exact,
+            locked_only: false,
+            min_title_votes: 0,
+            min_thumbnail_votes: 0,
+            prefer_timestamped_thumbnails: false,
+            random_frame_fallback: false,
+            prefer_custom_titles: false,
+            reviewed_title: ReviewedTitle::Silent,
+            skip_recapitalization_only,
+        }
+    }
+
+    fn branding(original: bool) -> BrandingResponse {
+        serde_json::from_value(serde_json::json!({
+            "titles": [{
+                "title": "Some Title",
+                "original": original,
+                "votes": 3,
+                "locked": false,
+                "UUID": "0a1b2c3d",
+            }],
+            "thumbnails": [],
+            "randomTime": 0.0,
+            "videoDuration": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn skips_recapitalizations_only_when_asked() {
+        assert!(policy(false).choose_title(&branding(true), false).is_ok());
+        assert!(matches!(
+            policy(true).choose_title(&branding(true), false),
+            Err(Skip::RecapitalizationOnly)
+        ));
+        assert!(policy(true).choose_title(&branding(false), false).is_ok());
+        // someone asked for it.
+        assert!(policy(true).choose_title(&branding(true), true).is_ok());
+    }
+
+    #[test]
+    fn treats_a_zero_duration_as_unknown() {
+        let mut policy = policy(false);
+        policy.min_video_duration = Some(60.0);
+
+        let mut branding = branding(false);
+        branding.video_duration = Some(0.0);
+        assert!(policy.choose_title(&branding, false).is_ok());
+
+        policy.unknown_duration = UnknownDuration::Skip;
+        assert!(matches!(
+            policy.choose_title(&branding, false),
+            Err(Skip::UnknownDuration)
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_the_random_frame_only_when_asked() {
+        let mut branding = branding(false);
+        branding.validate("dQw4w9WgXcQ");
+
+        assert!(matches!(
+            policy(false).choose_thumbnail(&branding, false),
+            Err(NoThumbnail::NoneSubmitted)
+        ));
+
+        let mut policy = policy(false);
+        policy.random_frame_fallback = true;
+        assert!(
+            policy
+                .choose_thumbnail(&branding, false)
+                .is_ok_and(|x| x.random)
+        );
+
+        // a random frame is never locked.
+        policy.thumbnail_mode = ThumbnailMode::OnlyLocked;
+        assert!(policy.choose_thumbnail(&branding, false).is_err());
+    }
+
+    fn title(text: &str, original: bool, votes: isize, locked: bool) -> BrandingTitle {
+        BrandingTitle {
+            title: text.to_string(),
+            original,
+            votes,
+            locked,
+            uuid: format!("uuid-{text}"),
+            removed: false,
+            shadow_hidden: false,
+        }
+    }
+
+    fn thumbnail(votes: isize, locked: bool, timestamp: Option<f32>) -> BrandingThumbnail {
+        BrandingThumbnail {
+            timestamp,
+            original: false,
+            votes,
+            locked,
+            uuid: format!("uuid-{votes}"),
+            removed: false,
+            shadow_hidden: false,
+            random: false,
+        }
+    }
+
+    fn video(
+        titles: Vec<BrandingTitle>,
+        thumbnails: Vec<BrandingThumbnail>,
+        duration: Option<f32>,
+    ) -> BrandingResponse {
+        BrandingResponse {
+            titles,
+            thumbnails,
+            random_time: 0.0,
+            video_duration: duration,
+            fetched_at: None,
+            service: None,
+            video_id: None,
+            random_frame: None,
+        }
+    }
+
+    /// What a case is called, the policy, the video, whether it was forced, the embed titles,
+    /// and which gate should fire if any.
+    type ReplyCase<'a> = (
+        &'a str,
+        Policy,
+        BrandingResponse,
+        bool,
+        &'a [String],
+        Option<&'a str>,
+    );
+
+    /// What a case is called, the policy, the thumbnails, whether one was asked for, and the
+    /// votes of the one chosen or which gate should fire.
+    type ThumbnailCase<'a> = (
+        &'a str,
+        Policy,
+        Vec<BrandingThumbnail>,
+        bool,
+        Result<isize, &'a str>,
+    );
+
+    /// The default policy, changed by `f`.
+    fn with(f: impl FnOnce(&mut Policy)) -> Policy {
+        let mut policy = policy(false);
+        f(&mut policy);
+        policy
+    }
+
+    /// The name of the gate a skip comes from, as in `TooShort`.
+    fn gate(skip: impl std::fmt::Debug) -> String {
+        let name = format!("{skip:?}");
+        name.split([' ', '(', '{'])
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    #[test]
+    fn should_reply_names_the_gate_that_fired() {
+        let fine = || vec![title("A Fine Title", false, 3, false)];
+        let mut removed = title("A Fine Title", false, 3, false);
+        removed.removed = true;
+        let embed = ["A Fine Title".to_string()];
+        let loose_embed = ["🔥 a fine TITLE!!".to_string()];
+
+        let cases: Vec<ReplyCase> = vec![
+            (
+                "trusted",
+                policy(false),
+                video(fine(), vec![], None),
+                false,
+                &[],
+                None,
+            ),
+            (
+                "no titles",
+                policy(false),
+                video(vec![], vec![], None),
+                false,
+                &[],
+                Some("NoTitle"),
+            ),
+            (
+                "only removed titles",
+                policy(false),
+                video(vec![removed], vec![], None),
+                false,
+                &[],
+                Some("NoTitle"),
+            ),
+            (
+                "too few votes",
+                with(|x| x.min_title_votes = 5),
+                video(fine(), vec![], None),
+                false,
+                &[],
+                Some("UntrustedTitle"),
+            ),
+            (
+                "not locked",
+                with(|x| x.locked_only = true),
+                video(fine(), vec![], None),
+                false,
+                &[],
+                Some("TitleNotLocked"),
+            ),
+            (
+                "too short",
+                with(|x| x.min_video_duration = Some(60.0)),
+                video(fine(), vec![], Some(30.0)),
+                false,
+                &[],
+                Some("TooShort"),
+            ),
+            (
+                "unknown duration",
+                with(|x| {
+                    x.min_video_duration = Some(60.0);
+                    x.unknown_duration = UnknownDuration::Skip;
+                }),
+                video(fine(), vec![], None),
+                false,
+                &[],
+                Some("UnknownDuration"),
+            ),
+            (
+                "reviewed",
+                policy(false),
+                video(vec![title("Original", true, 0, true)], vec![], None),
+                false,
+                &[],
+                Some("ReviewedFine"),
+            ),
+            (
+                "reviewed, replying anyway",
+                with(|x| x.reviewed_title = ReviewedTitle::Reply),
+                video(vec![title("Original", true, 0, true)], vec![], None),
+                false,
+                &[],
+                None,
+            ),
+            (
+                "recapitalized",
+                policy(true),
+                video(vec![title("Original", true, 3, false)], vec![], None),
+                false,
+                &[],
+                Some("RecapitalizationOnly"),
+            ),
+            (
+                "matching embed",
+                with(|x| x.skip_matching_embeds = true),
+                video(fine(), vec![], None),
+                false,
+                &embed,
+                Some("MatchesEmbed"),
+            ),
+            (
+                "loosely matching embed, compared exactly",
+                with(|x| x.skip_matching_embeds = true),
+                video(fine(), vec![], None),
+                false,
+                &loose_embed,
+                None,
+            ),
+            (
+                "loosely matching embed",
+                with(|x| {
+                    x.skip_matching_embeds = true;
+                    x.title_match = TitleMatch::Loose;
+                }),
+                video(fine(), vec![], None),
+                false,
+                &loose_embed,
+                Some("MatchesEmbed"),
+            ),
+            (
+                "forced past every gate",
+                with(|x| {
+                    x.min_title_votes = 5;
+                    x.locked_only = true;
+                    x.min_video_duration = Some(60.0);
+                    x.skip_matching_embeds = true;
+                }),
+                video(fine(), vec![], Some(30.0)),
+                true,
+                &embed,
+                None,
+            ),
+        ];
+
+        for (name, policy, branding, forced, embed_titles, expected) in cases {
+            let decision = policy.should_reply(ReplyDecisionInputs {
+                branding: &branding,
+                forced,
+                forced_thumbnail: false,
+                embed_titles,
+            });
+
+            let skipped = match decision {
+                Decision::Reply { .. } => None,
+                Decision::Skip(skip) => Some(gate(skip)),
+            };
+
+            assert_eq!(skipped.as_deref(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn gates_on_the_duration() {
+        let min = |unknown_duration| {
+            with(|x| {
+                x.min_video_duration = Some(60.0);
+                x.unknown_duration = unknown_duration;
+            })
+        };
+
+        for (duration, unknown, expected) in [
+            (Some(600.0), UnknownDuration::Process, None),
+            (Some(60.0), UnknownDuration::Process, None),
+            (Some(59.5), UnknownDuration::Process, Some("TooShort")),
+            (Some(59.5), UnknownDuration::Skip, Some("TooShort")),
+            (None, UnknownDuration::Process, None),
+            (None, UnknownDuration::Skip, Some("UnknownDuration")),
+            (Some(0.0), UnknownDuration::Process, None),
+            (Some(0.0), UnknownDuration::Skip, Some("UnknownDuration")),
+        ] {
+            let branding = video(
+                vec![title("A Fine Title", false, 3, false)],
+                vec![],
+                duration,
+            );
+            let skipped = min(unknown).choose_title(&branding, false).err().map(gate);
+
+            assert_eq!(skipped.as_deref(), expected, "{duration:?} {unknown}");
+        }
+
+        // no minimum, no gate.
+        let short = video(
+            vec![title("A Fine Title", false, 3, false)],
+            vec![],
+            Some(1.0),
+        );
+        assert!(policy(false).choose_title(&short, false).is_ok());
+    }
+
+    #[test]
+    fn gates_on_title_trust() {
+        for (votes, locked, min_title_votes, locked_only, expected) in [
+            (2, false, 2, false, None),
+            (1, false, 2, false, Some("UntrustedTitle")),
+            (-5, false, 0, false, Some("UntrustedTitle")),
+            // locked titles are trusted no matter the votes.
+            (-5, true, 2, false, None),
+            (10, false, 0, true, Some("TitleNotLocked")),
+            (0, true, 0, true, None),
+        ] {
+            let policy = with(|x| {
+                x.min_title_votes = min_title_votes;
+                x.locked_only = locked_only;
+            });
+            let branding = video(
+                vec![title("A Fine Title", false, votes, locked)],
+                vec![],
+                None,
+            );
+            let skipped = policy.choose_title(&branding, false).err().map(gate);
+
+            assert_eq!(
+                skipped.as_deref(),
+                expected,
+                "votes: {votes}, locked: {locked}, min: {min_title_votes}, locked only: {locked_only}"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_locked_submissions_below_the_vote_threshold() {
+        let policy = with(|x| {
+            x.min_title_votes = 3;
+            x.min_thumbnail_votes = 3;
+        });
+
+        for (votes, locked, kept) in [(2, false, false), (3, false, true), (-10, true, true)] {
+            let branding = video(
+                vec![title("A Fine Title", false, votes, locked)],
+                vec![thumbnail(votes, locked, None)],
+                None,
+            );
+
+            assert_eq!(
+                policy.choose_title(&branding, false).is_ok(),
+                kept,
+                "title, votes: {votes}, locked: {locked}"
+            );
+            assert_eq!(
+                policy.choose_thumbnail(&branding, false).is_ok(),
+                kept,
+                "thumbnail, votes: {votes}, locked: {locked}"
+            );
+        }
+    }
+
+    #[test]
+    fn selects_titles() {
+        let titles = || {
+            vec![
+                title("First", false, 1, false),
+                title("Most Votes", false, 5, false),
+                title("Tied", false, 5, false),
+                title("Locked", false, 2, true),
+            ]
+        };
+
+        for (selection, titles, expected) in [
+            (TitleSelection::First, titles(), "First"),
+            (TitleSelection::MostVotes, titles(), "Most Votes"),
+            (TitleSelection::LockedFirst, titles(), "Locked"),
+            (TitleSelection::LockedFirst, titles()[..3].to_vec(), "First"),
+        ] {
+            let policy = with(|x| x.title_selection = selection);
+            let branding = video(titles, vec![], None);
+
+            assert_eq!(
+                policy.choose_title(&branding, false).unwrap().title,
+                expected,
+                "{selection}"
+            );
+        }
+    }
+
+    #[test]
+    fn prefers_custom_titles_when_asked() {
+        let recapitalized = video(
+            vec![
+                title("Original", true, 5, false),
+                title("Rewrite", false, 2, false),
+            ],
+            vec![],
+            None,
+        );
+        let locked_in = video(
+            vec![
+                title("Original", true, 5, true),
+                title("Rewrite", false, 2, false),
+            ],
+            vec![],
+            None,
+        );
+
+        let prefer = with(|x| x.prefer_custom_titles = true);
+
+        assert_eq!(
+            policy(false)
+                .choose_title(&recapitalized, false)
+                .unwrap()
+                .title,
+            "Original"
+        );
+        assert_eq!(
+            prefer.choose_title(&recapitalized, false).unwrap().title,
+            "Rewrite"
+        );
+        // the recapitalization is locked in, the rewrite isn't.
+        assert!(matches!(
+            prefer.choose_title(&locked_in, false),
+            Err(Skip::ReviewedFine)
+        ));
+    }
+
+    #[test]
+    fn prefers_custom_titles_over_mixed_candidates() {
+        let prefer = with(|x| x.prefer_custom_titles = true);
+
+        for (name, titles, expected) in [
+            (
+                "untrusted rewrite",
+                vec![
+                    title("Original", true, 5, false),
+                    title("Rewrite", false, -1, false),
+                ],
+                Ok("Original"),
+            ),
+            (
+                "later trusted rewrite",
+                vec![
+                    title("Original", true, 5, false),
+                    title("Untrusted", false, -1, false),
+                    title("Rewrite", false, 1, false),
+                ],
+                Ok("Rewrite"),
+            ),
+            (
+                "only the rewrite is locked",
+                vec![
+                    title("Original", true, 5, false),
+                    title("Rewrite", false, 0, true),
+                ],
+                Ok("Rewrite"),
+            ),
+            (
+                "both locked",
+                vec![
+                    title("Original", true, 5, true),
+                    title("Rewrite", false, 0, true),
+                ],
+                Ok("Rewrite"),
+            ),
+            (
+                "custom first",
+                vec![
+                    title("Rewrite", false, 5, false),
+                    title("Original", true, 9, true),
+                ],
+                Ok("Rewrite"),
+            ),
+            (
+                "no rewrite",
+                vec![title("Original", true, 5, true)],
+                Err("ReviewedFine".to_string()),
+            ),
+        ] {
+            let branding = video(titles, vec![], None);
+            let chosen = prefer
+                .choose_title(&branding, false)
+                .map(|x| x.title.as_str())
+                .map_err(gate);
+
+            assert_eq!(chosen, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn gates_thumbnails_by_mode() {
+        let unlocked = || vec![thumbnail(1, false, None), thumbnail(3, false, None)];
+        let timestamped = || vec![thumbnail(5, false, None), thumbnail(1, false, Some(10.0))];
+
+        let cases: Vec<ThumbnailCase> = vec![
+            (
+                "disabled",
+                with(|x| x.thumbnail_mode = ThumbnailMode::Disabled),
+                unlocked(),
+                false,
+                Err("Disabled"),
+            ),
+            (
+                "disabled, but asked for",
+                with(|x| x.thumbnail_mode = ThumbnailMode::Disabled),
+                unlocked(),
+                true,
+                Ok(3),
+            ),
+            ("enabled", policy(false), unlocked(), false, Ok(3)),
+            (
+                "none submitted",
+                policy(false),
+                vec![],
+                false,
+                Err("NoneSubmitted"),
+            ),
+            (
+                "too few votes",
+                with(|x| x.min_thumbnail_votes = 5),
+                unlocked(),
+                false,
+                Err("Untrusted"),
+            ),
+            (
+                "only locked",
+                with(|x| x.thumbnail_mode = ThumbnailMode::OnlyLocked),
+                unlocked(),
+                false,
+                Err("NotLocked"),
+            ),
+            (
+                "only locked, one is",
+                with(|x| x.thumbnail_mode = ThumbnailMode::OnlyLocked),
+                vec![thumbnail(3, false, None), thumbnail(1, true, None)],
+                false,
+                Ok(1),
+            ),
+            (
+                "locked only",
+                with(|x| x.locked_only = true),
+                unlocked(),
+                false,
+                Err("NotLocked"),
+            ),
+            ("timestamped", policy(false), timestamped(), false, Ok(5)),
+            (
+                "timestamped, preferred",
+                with(|x| x.prefer_timestamped_thumbnails = true),
+                timestamped(),
+                false,
+                Ok(1),
+            ),
+        ];
+
+        for (name, policy, thumbnails, forced, expected) in cases {
+            let branding = video(vec![], thumbnails, None);
+            let chosen = policy
+                .choose_thumbnail(&branding, forced)
+                .map(|x| x.votes)
+                .map_err(gate);
+
+            assert_eq!(chosen, expected.map_err(str::to_string), "{name}");
+        }
+    }
+
+    #[test]
+    fn looks_past_an_untrusted_first_thumbnail() {
+        let branding = video(
+            vec![],
+            vec![
+                thumbnail(-2, false, Some(5.0)),
+                thumbnail(0, false, None),
+                thumbnail(0, true, Some(30.0)),
+            ],
+            None,
+        );
+
+        for policy in [
+            with(|x| x.min_thumbnail_votes = 1),
+            with(|x| x.thumbnail_mode = ThumbnailMode::OnlyLocked),
+        ] {
+            let chosen = policy.choose_thumbnail(&branding, false).unwrap();
+            assert!(chosen.locked);
+            assert_eq!(chosen.timestamp, Some(30.0));
+        }
+    }
+
+    #[test]
+    fn prefers_timestamped_thumbnails_among_trusted_ones() {
+        let prefer = |f: fn(&mut Policy)| {
+            with(|x| {
+                x.prefer_timestamped_thumbnails = true;
+                f(x);
+            })
+        };
+
+        for (name, policy, thumbnails, expected) in [
+            (
+                "most voted timestamped",
+                prefer(|_| {}),
+                vec![
+                    thumbnail(9, false, None),
+                    thumbnail(1, false, Some(1.0)),
+                    thumbnail(3, false, Some(3.0)),
+                ],
+                (3, Some(3.0)),
+            ),
+            (
+                "timestamped but downvoted",
+                prefer(|_| {}),
+                vec![thumbnail(9, false, None), thumbnail(-1, false, Some(1.0))],
+                (9, None),
+            ),
+            (
+                "timestamped but not locked",
+                prefer(|x| x.thumbnail_mode = ThumbnailMode::OnlyLocked),
+                vec![thumbnail(0, true, None), thumbnail(9, false, Some(1.0))],
+                (0, None),
+            ),
+            (
+                "none timestamped",
+                prefer(|_| {}),
+                vec![thumbnail(1, false, None), thumbnail(2, false, None)],
+                (2, None),
+            ),
+        ] {
+            let branding = video(vec![], thumbnails, None);
+            let chosen = policy.choose_thumbnail(&branding, false).unwrap();
+
+            assert_eq!((chosen.votes, chosen.timestamp), expected, "{name}");
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Editing messages without hammering the same one in quick succession.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::all::{CacheHttp, EditMessage, Message, MessageId};
+
+use crate::state::Sweep;
+
+/// Spaces out edits to the same message, so features that each want to edit it (suppressing
+/// the embed, adding a late thumbnail, ...) don't trip rate limits or make it flicker.
+pub struct EditScheduler {
+    next: Mutex<HashMap<MessageId, Instant>>,
+    interval: Duration,
+}
+
+impl EditScheduler {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            next: Mutex::new(HashMap::new()),
+            interval,
+        }
+    }
+
+    /// Waits until `msg_id` may be edited again, and reserves the slot after that for the
+    /// next edit. Edits to the same message run in the order they got here.
+    async fn wait_turn(&self, msg_id: MessageId) {
+        let now = Instant::now();
+
+        let at = {
+            let Ok(mut next) = self.next.lock() else {
+                return;
+            };
+
+            let at = next
+                .get(&msg_id)
+                .copied()
+                .filter(|x| *x > now)
+                .unwrap_or(now);
+            next.insert(msg_id, at + self.interval);
+            at
+        };
+
+        if at > now {
+            log::info!(
+                "deferring edit of message {msg_id} by {}ms.",
+                (at - now).as_millis()
+            );
+            tokio::time::sleep_until(at.into()).await;
+        }
+    }
+
+    pub async fn edit(
+        &self,
+        cache_http: impl CacheHttp,
+        msg: &mut Message,
+        edit: EditMessage,
+    ) -> serenity::Result<()> {
+        self.wait_turn(msg.id).await;
+        msg.edit(cache_http, edit).await
+    }
+}
+
+impl Sweep for EditScheduler {
+    fn sweep(&self) -> usize {
+        let now = Instant::now();
+
+        let Ok(mut next) = self.next.lock() else {
+            return 0;
+        };
+
+        let before = next.len();
+        next.retain(|_, at| *at > now);
+
+        before - next.len()
+    }
+}
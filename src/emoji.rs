@@ -0,0 +1,148 @@
+//! Emoji the bot uses, which guilds can swap out for their own custom ones.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serenity::all::{Cache, EmojiId, GuildId, ReactionType};
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum EmojiSlot {
+    /// Prefixes plain-text replies.
+    Compact,
+    /// The reaction with which moderators have a message processed again.
+    Retrigger,
+}
+
+impl EmojiSlot {
+    fn default_unicode(&self) -> &'static str {
+        match self {
+            EmojiSlot::Compact => "🏹",
+            EmojiSlot::Retrigger => "🔁",
+        }
+    }
+}
+
+impl FromStr for EmojiSlot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "compact" => Ok(EmojiSlot::Compact),
+            "retrigger" => Ok(EmojiSlot::Retrigger),
+            _ => Err(anyhow::anyhow!("can't parse emoji slot")),
+        }
+    }
+}
+
+/// A `<guild id>:<slot>:<emoji id>` override, as passed on the command line.
+#[derive(Clone, Debug)]
+pub struct GuildEmoji {
+    pub guild_id: GuildId,
+    pub slot: EmojiSlot,
+    pub emoji_id: EmojiId,
+}
+
+impl FromStr for GuildEmoji {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+
+        let (Some(guild_id), Some(slot), Some(emoji_id), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("expected <guild id>:<slot>:<emoji id>");
+        };
+
+        let guild_id = guild_id.trim().parse::<std::num::NonZeroU64>()?;
+        let emoji_id = emoji_id.trim().parse::<std::num::NonZeroU64>()?;
+
+        Ok(GuildEmoji {
+            guild_id: guild_id.into(),
+            slot: slot.trim().parse()?,
+            emoji_id: emoji_id.into(),
+        })
+    }
+}
+
+pub struct GuildEmojis {
+    overrides: HashMap<(GuildId, EmojiSlot), EmojiId>,
+}
+
+impl GuildEmojis {
+    pub fn new(overrides: &[GuildEmoji]) -> Self {
+        Self {
+            overrides: overrides
+                .iter()
+                .map(|x| ((x.guild_id, x.slot), x.emoji_id))
+                .collect(),
+        }
+    }
+
+    /// The emoji to use for `slot` in a guild. Falls back to the unicode default outside of
+    /// guilds, without an override, or if the guild doesn't have the configured emoji (anymore).
+    pub fn resolve(
+        &self,
+        cache: &Cache,
+        guild_id: Option<GuildId>,
+        slot: EmojiSlot,
+    ) -> ReactionType {
+        let custom = guild_id.and_then(|guild_id| {
+            let emoji_id = self.overrides.get(&(guild_id, slot))?;
+            let guild = cache.guild(guild_id)?;
+
+            match guild.emojis.get(emoji_id) {
+                Some(emoji) if emoji.available => Some(ReactionType::from(emoji.clone())),
+                _ => {
+                    log::debug!("guild {guild_id} doesn't have emoji {emoji_id} for {slot:?}.");
+                    None
+                }
+            }
+        });
+
+        custom.unwrap_or_else(|| ReactionType::Unicode(slot.default_unicode().to_string()))
+    }
+
+    /// Whether `emoji` is the one [`resolve`](Self::resolve) picks for `slot` in a guild.
+    pub fn is(
+        &self,
+        cache: &Cache,
+        guild_id: Option<GuildId>,
+        slot: EmojiSlot,
+        emoji: &ReactionType,
+    ) -> bool {
+        match (self.resolve(cache, guild_id, slot), emoji) {
+            // clients may or may not send the emoji presentation selector along.
+            (ReactionType::Unicode(x), ReactionType::Unicode(y)) => {
+                x.trim_end_matches('\u{FE0F}') == y.trim_end_matches('\u{FE0F}')
+            }
+            (ReactionType::Custom { id: x, .. }, ReactionType::Custom { id: y, .. }) => x == *y,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_guild_emoji_overrides() {
+        let parsed: GuildEmoji = " 1 : Retrigger : 2 ".parse().unwrap();
+        assert_eq!(parsed.guild_id, GuildId::new(1));
+        assert_eq!(parsed.slot, EmojiSlot::Retrigger);
+        assert_eq!(parsed.emoji_id, EmojiId::new(2));
+
+        for invalid in [
+            "",
+            "1:compact",
+            "1:compact:2:3",
+            "0:compact:2",
+            "1:compact:0",
+            "x:compact:2",
+            "1:processing:2",
+        ] {
+            assert!(invalid.parse::<GuildEmoji>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! How a request to DeArrow can fail, so callers can tell the normal "no data" from actual
+//! trouble.
+
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum DeArrowError {
+    /// A 404, which just means nothing was submitted or generated for the video.
+    NotFound,
+    /// Any other unsuccessful status.
+    Http(StatusCode),
+    /// No answer at all: the connection, a timeout, TLS, ...
+    Network(reqwest::Error),
+    /// An answer we couldn't make sense of, like malformed JSON or a broken image.
+    Decode(anyhow::Error),
+    /// A thumbnail too large to attach, even recompressed.
+    TooLarge,
+}
+
+impl DeArrowError {
+    /// Whether the request may well work out on the next try: the network, or a 5xx. A 4xx or
+    /// an answer we couldn't make sense of will fail the same way again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DeArrowError::Network(_) => true,
+            DeArrowError::Http(status) => status.is_server_error(),
+            DeArrowError::NotFound | DeArrowError::Decode(_) | DeArrowError::TooLarge => false,
+        }
+    }
+}
+
+impl std::fmt::Display for DeArrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeArrowError::NotFound => write!(f, "not found"),
+            DeArrowError::Http(status) => write!(f, "unsuccessful status {status}"),
+            DeArrowError::Network(e) => write!(f, "request failed: {e}"),
+            DeArrowError::Decode(e) => write!(f, "unexpected response: {e:#}"),
+            DeArrowError::TooLarge => write!(f, "too large to attach, even recompressed"),
+        }
+    }
+}
+
+impl std::error::Error for DeArrowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeArrowError::Network(e) => Some(e),
+            DeArrowError::Decode(e) => Some(e.as_ref()),
+            DeArrowError::NotFound | DeArrowError::Http(_) | DeArrowError::TooLarge => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for DeArrowError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(StatusCode::NOT_FOUND) => DeArrowError::NotFound,
+            Some(status) => DeArrowError::Http(status),
+            None if e.is_decode() => DeArrowError::Decode(e.into()),
+            None => DeArrowError::Network(e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for DeArrowError {
+    fn from(e: anyhow::Error) -> Self {
+        DeArrowError::Decode(e)
+    }
+}
+
+/// Runs `request` up to `attempts` times, for as long as it fails transiently, backing off
+/// exponentially with some jitter in between.
+pub async fn retry_transient<T, F, Fut>(
+    attempts: u32,
+    what: &str,
+    mut request: F,
+) -> Result<T, DeArrowError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DeArrowError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match request().await {
+            Err(e) if attempt < attempts && e.is_transient() => {
+                let backoff = 250u64 << (attempt - 1).min(6);
+                // no need for a proper rng to keep retries from lining up.
+                let jitter = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .subsec_nanos() as u64
+                    % (backoff / 2 + 1);
+                let delay = std::time::Duration::from_millis(backoff + jitter);
+
+                log::warn!("{what} failed, retrying in {}ms: {e:#}", delay.as_millis());
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_server_errors_are_transient() {
+        assert!(DeArrowError::Http(StatusCode::BAD_GATEWAY).is_transient());
+        assert!(!DeArrowError::Http(StatusCode::TOO_MANY_REQUESTS).is_transient());
+        assert!(!DeArrowError::NotFound.is_transient());
+        assert!(!DeArrowError::Decode(anyhow::anyhow!("bad json")).is_transient());
+    }
+
+    /// Runs [`retry_transient`] over `errors`, one per attempt until they run out, which
+    /// succeeds. Returns the result and how many attempts were made.
+    async fn retry(attempts: u32, errors: Vec<DeArrowError>) -> (Result<(), DeArrowError>, usize) {
+        let mut errors = errors.into_iter();
+        let mut made = 0;
+
+        let res = retry_transient(attempts, "testing", || {
+            made += 1;
+            let res = errors.next().map_or(Ok(()), Err);
+            async move { res }
+        })
+        .await;
+
+        (res, made)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn doesnt_retry_a_404() {
+        let (res, made) = retry(3, vec![DeArrowError::NotFound]).await;
+
+        assert!(matches!(res, Err(DeArrowError::NotFound)));
+        assert_eq!(made, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_a_503() {
+        let (res, made) = retry(3, vec![DeArrowError::Http(StatusCode::SERVICE_UNAVAILABLE)]).await;
+
+        assert!(res.is_ok());
+        assert_eq!(made, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_last_attempt() {
+        let errors = (0..5)
+            .map(|_| DeArrowError::Http(StatusCode::BAD_GATEWAY))
+            .collect();
+        let (res, made) = retry(3, errors).await;
+
+        assert!(matches!(
+            res,
+            Err(DeArrowError::Http(StatusCode::BAD_GATEWAY))
+        ));
+        assert_eq!(made, 3);
+    }
+}
@@ -0,0 +1,57 @@
+//! Outbound notifications about de-clickbaits, for external integrations.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(serde::Serialize)]
+pub struct DeclickbaitEvent {
+    pub video_id: String,
+    pub guild_id: Option<u64>,
+    pub channel_id: u64,
+    pub title: String,
+    pub title_uuid: String,
+    pub title_votes: isize,
+    pub title_locked: bool,
+    pub trust_tier: String,
+    pub thumbnail_uuid: Option<String>,
+    pub thumbnail_votes: Option<isize>,
+    pub thumbnail_locked: Option<bool>,
+    /// Unix timestamp of the reply, in seconds.
+    pub timestamp: u64,
+}
+
+impl DeclickbaitEvent {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// POSTs every de-clickbait as JSON to a URL.
+pub struct EventWebhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl EventWebhook {
+    pub fn new(url: String) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()?,
+            url,
+        })
+    }
+
+    /// Sends the event in the background, so a slow receiver never holds up the handler.
+    pub fn emit(&self, event: DeclickbaitEvent) {
+        let request = self.client.post(&self.url).json(&event);
+
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|x| x.error_for_status()) {
+                log::warn!("failed to deliver event for {}: {e:#?}", event.video_id);
+            }
+        });
+    }
+}
@@ -0,0 +1,83 @@
+//! Serving the few HTTP endpoints meant for infrastructure rather than people: [`crate::metrics`]
+//! for Prometheus to scrape, and [`crate::health`] for probes.
+//!
+//! Both are a single `GET` of a fixed path, so this speaks just enough HTTP/1.1 for that rather
+//! than pulling in a web framework.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Answers a `GET` of a path with a status line and body, `None` if there's nothing there.
+pub type Route = dyn Fn(&str) -> Option<(&'static str, String)> + Send + Sync;
+
+/// Requests from a scraper or probe fit in far less, anything bigger isn't one.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// How long a connection gets to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves `route` on `addr` in the background.
+pub async fn spawn(addr: SocketAddr, route: Arc<Route>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let route = route.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, route.as_ref()).await {
+                            log::debug!("request on {addr} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => log::warn!("could not accept connection on {addr}: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, route: &Route) -> anyhow::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+
+    // only the request line matters, but the headers are read so the client isn't cut off.
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_BYTES {
+            anyhow::bail!("request too large");
+        }
+
+        let read = tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buf)).await??;
+        if read == 0 {
+            anyhow::bail!("connection closed before the request was complete");
+        }
+
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => {
+            route(path).unwrap_or_else(|| ("404 Not Found", "not found\n".to_string()))
+        }
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Telling being removed from a guild apart from the guild going unavailable for a bit.
+//!
+//! Discord sends a `guild_delete` for both. It marks the ones from outages as unavailable, but
+//! around reconnects a guild can still briefly look removed, so a removal only counts once the
+//! guild stayed away for a grace period.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::all::GuildId;
+
+/// The guilds we were removed from, and since when.
+pub struct Departures {
+    left: Mutex<HashMap<GuildId, Instant>>,
+    grace: Duration,
+}
+
+impl Departures {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            left: Mutex::new(HashMap::new()),
+            grace,
+        }
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// Notes a `guild_delete` at `at`. Returns whether it looks like a removal, which starts
+    /// its grace period. An unavailable guild is just an outage, and is expected back.
+    pub fn left(&self, guild_id: GuildId, unavailable: bool, at: Instant) -> bool {
+        if unavailable {
+            return false;
+        }
+
+        if let Ok(mut left) = self.left.lock() {
+            left.insert(guild_id, at);
+        }
+
+        true
+    }
+
+    /// Notes a `guild_create`, calling off the guild's removal.
+    pub fn returned(&self, guild_id: GuildId) {
+        if let Ok(mut left) = self.left.lock() {
+            left.remove(&guild_id);
+        }
+    }
+
+    /// Whether the guild still counts as removed at `at`, having stayed away for the whole
+    /// grace period since it last left. It's only ever confirmed once.
+    pub fn confirm(&self, guild_id: GuildId, at: Instant) -> bool {
+        let Ok(mut left) = self.left.lock() else {
+            return false;
+        };
+
+        match left.get(&guild_id) {
+            Some(since) if at.saturating_duration_since(*since) >= self.grace => {
+                left.remove(&guild_id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn an_unavailable_guild_is_never_removed() {
+        let departures = Departures::new(GRACE);
+        let at = Instant::now();
+
+        assert!(!departures.left(GuildId::new(1), true, at));
+        assert!(!departures.confirm(GuildId::new(1), at + GRACE * 10));
+    }
+
+    #[test]
+    fn a_removed_guild_is_removed_after_the_grace_period() {
+        let departures = Departures::new(GRACE);
+        let at = Instant::now();
+
+        assert!(departures.left(GuildId::new(1), false, at));
+        assert!(!departures.confirm(GuildId::new(1), at + GRACE / 2));
+        assert!(departures.confirm(GuildId::new(1), at + GRACE));
+        // only once.
+        assert!(!departures.confirm(GuildId::new(1), at + GRACE * 2));
+    }
+
+    #[test]
+    fn a_guild_that_came_back_isnt_removed() {
+        let departures = Departures::new(GRACE);
+        let at = Instant::now();
+
+        departures.left(GuildId::new(1), false, at);
+        departures.left(GuildId::new(2), false, at);
+        departures.returned(GuildId::new(1));
+
+        assert!(!departures.confirm(GuildId::new(1), at + GRACE));
+        assert!(departures.confirm(GuildId::new(2), at + GRACE));
+    }
+
+    #[test]
+    fn leaving_again_restarts_the_grace_period() {
+        let departures = Departures::new(GRACE);
+        let at = Instant::now();
+
+        departures.left(GuildId::new(1), false, at);
+        departures.returned(GuildId::new(1));
+        departures.left(GuildId::new(1), false, at + GRACE / 2);
+
+        // the first departure's check comes too early for the second one.
+        assert!(!departures.confirm(GuildId::new(1), at + GRACE));
+        assert!(departures.confirm(GuildId::new(1), at + GRACE + GRACE / 2));
+    }
+}
@@ -0,0 +1,76 @@
+//! Whether the bot is connected to the gateway, for liveness and readiness probes.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serenity::all::ShardId;
+use serenity::gateway::ConnectionStage;
+
+/// The connection state of every shard the gateway told us about.
+#[derive(Default)]
+pub struct Health {
+    shards: Mutex<HashMap<ShardId, bool>>,
+}
+
+impl Health {
+    /// Marks the shard connected, once it's ready or resumed.
+    pub fn connected(&self, shard_id: ShardId) {
+        if let Ok(mut shards) = self.shards.lock() {
+            shards.insert(shard_id, true);
+        }
+    }
+
+    /// Follows the shard's connection stage, so a dropped connection counts against us until
+    /// serenity has it back.
+    pub fn stage(&self, shard_id: ShardId, stage: ConnectionStage) {
+        if let Ok(mut shards) = self.shards.lock() {
+            shards.insert(shard_id, stage == ConnectionStage::Connected);
+        }
+    }
+
+    /// Whether there's a shard, and they're all connected.
+    pub fn is_healthy(&self) -> bool {
+        self.shards
+            .lock()
+            .is_ok_and(|shards| !shards.is_empty() && shards.values().all(|x| *x))
+    }
+
+    /// Answers `/healthz` with 200 while healthy, 503 otherwise.
+    pub fn route(&self, path: &str) -> Option<(&'static str, String)> {
+        if path != "/healthz" {
+            return None;
+        }
+
+        if self.is_healthy() {
+            Some(("200 OK", "ok\n".to_string()))
+        } else {
+            Some(("503 Service Unavailable", "not connected\n".to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_only_while_every_shard_is_connected() {
+        let health = Health::default();
+        assert!(!health.is_healthy());
+
+        health.connected(ShardId(0));
+        health.connected(ShardId(1));
+        assert!(health.is_healthy());
+
+        health.stage(ShardId(1), ConnectionStage::Resuming);
+        assert!(!health.is_healthy());
+        assert_eq!(
+            health.route("/healthz").unwrap().0,
+            "503 Service Unavailable"
+        );
+
+        health.stage(ShardId(1), ConnectionStage::Connected);
+        assert_eq!(health.route("/healthz").unwrap().0, "200 OK");
+        assert!(health.route("/metrics").is_none());
+    }
+}
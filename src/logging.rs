@@ -0,0 +1,79 @@
+//! Setting up the logger, either for people reading the terminal or for a log aggregator.
+
+use std::io::Write;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+/// How log lines are written.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LogFormat {
+    /// One human-readable line per record.
+    Text,
+    /// One JSON object per line, with the record's key-values (like `video_id`) as fields.
+    Json,
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::anyhow!("can't parse log format")),
+        }
+    }
+}
+
+/// Collects a record's key-values into the JSON object it's written as.
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        self.0
+            .insert(key.to_string(), Value::String(value.to_string()));
+        Ok(())
+    }
+}
+
+/// Installs the logger. Our own logs may go down to trace, the global max level set by the
+/// caller is what actually applies.
+pub fn init(format: LogFormat) {
+    let mut builder = colog::default_builder();
+
+    builder
+        .default_format()
+        .filter(Some("serenity"), log::LevelFilter::Warn)
+        .filter(Some("tracing::span"), log::LevelFilter::Warn)
+        .filter_level(log::LevelFilter::Info)
+        // lets /loglevel go all the way for our own logs.
+        .filter(Some(env!("CARGO_CRATE_NAME")), log::LevelFilter::Trace);
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert("timestamp".into(), buf.timestamp().to_string().into());
+            line.insert("level".into(), record.level().as_str().into());
+            line.insert("target".into(), record.target().into());
+            line.insert("message".into(), record.args().to_string().into());
+
+            // a key-value that can't be read just goes missing from the line.
+            let _ = record.key_values().visit(&mut Fields(&mut line));
+
+            writeln!(buf, "{}", Value::Object(line))
+        });
+    }
+
+    builder.init();
+}
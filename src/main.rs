@@ -1,18 +1,51 @@
-#![allow(unused)]
-
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU64;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
 
-use clap::{Parser, ValueEnum};
+use clap::Parser;
 use futures::StreamExt;
 use regex::Regex;
 use serenity::all::{
-    CreateAttachment, CreateEmbed, CreateEmbedFooter, CreateMessage, EditMessage, Event,
-    MessageUpdateEvent,
+    ChannelId, ChannelType, Colour, Command, CreateAttachment, CreateButton, CreateEmbed,
+    CreateEmbedAuthor, CreateEmbedFooter, EditMessage, Embed, Event, GuildChannel, GuildId,
+    Interaction, MessageId, MessageUpdateEvent, Permissions, Reaction, Ready, Timestamp, UserId,
 };
 use serenity::async_trait;
 use serenity::model::channel::Message;
 use serenity::prelude::*;
 
+use error::{DeArrowError, retry_transient};
+
+mod cache;
+mod commands;
+mod config;
+mod decision;
+mod edits;
+mod emoji;
+mod error;
+mod events;
+mod exporter;
+mod guilds;
+mod health;
+mod logging;
+mod maintenance;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod optout;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod permissions;
+mod recompress;
+mod reply;
+mod schedule;
+mod state;
+mod summary;
+mod trust;
+mod youtube;
+
 // TODO: command to force a title response
 // TODO: command to ask for reason of nonaction
 
@@ -23,9 +56,9 @@ enum ThumbnailMode {
     OnlyLocked,
 }
 
-impl ToString for ThumbnailMode {
-    fn to_string(&self) -> String {
-        format!("{:?}", self)
+impl std::fmt::Display for ThumbnailMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
@@ -42,7 +75,241 @@ impl FromStr for ThumbnailMode {
     }
 }
 
-#[derive(serde::Deserialize)]
+/// What to do with videos DeArrow doesn't know the duration of when `--min-video-duration` is set.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum UnknownDuration {
+    Process,
+    Skip,
+}
+
+impl std::fmt::Display for UnknownDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for UnknownDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "process" => Ok(UnknownDuration::Process),
+            "skip" => Ok(UnknownDuration::Skip),
+            _ => Err(anyhow::anyhow!("can't parse unknown duration behavior")),
+        }
+    }
+}
+
+/// Which of the visible titles DeArrow returned is considered the best one.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum TitleSelection {
+    /// The first one, in the order DeArrow ranks them.
+    First,
+    /// The one with the most votes, the earlier one on ties.
+    MostVotes,
+    /// The first locked one, or the first one if none are locked.
+    LockedFirst,
+}
+
+impl std::fmt::Display for TitleSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for TitleSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(TitleSelection::First),
+            "mostvotes" => Ok(TitleSelection::MostVotes),
+            "lockedfirst" => Ok(TitleSelection::LockedFirst),
+            _ => Err(anyhow::anyhow!("can't parse title selection")),
+        }
+    }
+}
+
+/// What to do with messages longer than `--max-content-scan-len`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum OversizedContent {
+    /// Look for links in the first `--max-content-scan-len` bytes only.
+    Prefix,
+    /// Don't look for links at all.
+    Skip,
+}
+
+impl OversizedContent {
+    /// The part of `content` to look for links in, if any, given at most `max_len` bytes are.
+    fn scannable(self, content: &str, max_len: usize) -> Option<&str> {
+        if content.len() <= max_len {
+            return Some(content);
+        }
+
+        match self {
+            OversizedContent::Skip => {
+                log::debug!(
+                    "not looking through a {} byte message, it's over --max-content-scan-len.",
+                    content.len()
+                );
+                None
+            }
+            OversizedContent::Prefix => {
+                let mut end = max_len;
+                while !content.is_char_boundary(end) {
+                    end -= 1;
+                }
+
+                log::debug!(
+                    "only looking through the first {end} bytes of a {} byte message.",
+                    content.len()
+                );
+                Some(&content[..end])
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for OversizedContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for OversizedContent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "prefix" => Ok(OversizedContent::Prefix),
+            "skip" => Ok(OversizedContent::Skip),
+            _ => Err(anyhow::anyhow!("can't parse oversized content behavior")),
+        }
+    }
+}
+
+/// An embed accent color as passed on the command line, in hex like `#00aaff`.
+#[derive(Clone, Copy, Debug)]
+struct EmbedColor(Colour);
+
+impl FromStr for EmbedColor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+
+        if hex.len() != 6 || !hex.chars().all(|x| x.is_ascii_hexdigit()) {
+            anyhow::bail!("can't parse embed color, expected 6 hex digits like #00aaff");
+        }
+
+        Ok(EmbedColor(Colour::new(u32::from_str_radix(hex, 16)?)))
+    }
+}
+
+/// How replies are laid out.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum OutputStyle {
+    Embed,
+    /// The embed, followed by link buttons. Discord's newer layout components (containers,
+    /// sections, media galleries) aren't supported by serenity 0.12 yet, so until they are this
+    /// falls back to the classic embed for everything else.
+    Components,
+}
+
+impl std::fmt::Display for OutputStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for OutputStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "embed" => Ok(OutputStyle::Embed),
+            "components" => Ok(OutputStyle::Components),
+            _ => Err(anyhow::anyhow!("can't parse output style")),
+        }
+    }
+}
+
+/// What to do about videos whose original title the community locked in as fine.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum ReviewedTitle {
+    /// Don't reply, restating the title is just noise.
+    Silent,
+    /// React with ✅ to tell that DeArrow reviewed it.
+    React,
+    /// Reply like for any other video.
+    Reply,
+}
+
+impl std::fmt::Display for ReviewedTitle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for ReviewedTitle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "silent" => Ok(ReviewedTitle::Silent),
+            "react" => Ok(ReviewedTitle::React),
+            "reply" => Ok(ReviewedTitle::Reply),
+            _ => Err(anyhow::anyhow!("can't parse reviewed title behavior")),
+        }
+    }
+}
+
+/// How the original embed's title is compared to the de-arrowed one for `--skip-matching-embeds`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum TitleMatch {
+    Exact,
+    /// Ignores case, emoji and punctuation, so only substantive changes count.
+    Loose,
+}
+
+impl TitleMatch {
+    fn matches(self, a: &str, b: &str) -> bool {
+        match self {
+            TitleMatch::Exact => a.trim() == b.trim(),
+            TitleMatch::Loose => normalize_title(a) == normalize_title(b),
+        }
+    }
+}
+
+impl std::fmt::Display for TitleMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for TitleMatch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "exact" => Ok(TitleMatch::Exact),
+            "loose" => Ok(TitleMatch::Loose),
+            _ => Err(anyhow::anyhow!("can't parse title match mode")),
+        }
+    }
+}
+
+/// Lowercases `title` and drops emoji and punctuation, so `🔥 INSANE!!!` becomes `insane`.
+fn normalize_title(title: &str) -> String {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(serde::Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct BrandingTitle {
     title: String,
@@ -51,301 +318,3278 @@ struct BrandingTitle {
     locked: bool,
     #[serde(rename = "UUID")]
     uuid: String,
+    #[serde(default)]
+    removed: bool,
+    #[serde(default)]
+    shadow_hidden: bool,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct BrandingThumbnail {
     timestamp: Option<f32>,
+    /// A vote for keeping the video's own thumbnail.
     original: bool,
     votes: isize,
     locked: bool,
     #[serde(rename = "UUID")]
     uuid: String,
+    #[serde(default)]
+    removed: bool,
+    #[serde(default)]
+    shadow_hidden: bool,
+    /// Not submitted by anyone, but a frame at the video's `random_time`.
+    #[serde(skip)]
+    random: bool,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct BrandingResponse {
     titles: Vec<BrandingTitle>,
     thumbnails: Vec<BrandingThumbnail>,
     random_time: f32,
     video_duration: Option<f32>,
+    /// When we got this from DeArrow, which for cached branding can be a while ago.
+    #[serde(skip)]
+    fetched_at: Option<std::time::SystemTime>,
+    /// Not sent by DeArrow itself, but by mirrors serving several services.
+    #[serde(default)]
+    service: Option<String>,
+    #[serde(default, rename = "videoID")]
+    video_id: Option<String>,
+    /// The frame at `random_time`, for when no submitted thumbnail is good enough.
+    #[serde(skip)]
+    random_frame: Option<BrandingThumbnail>,
 }
 
-async fn get_thumbnail(vid_id: &str, timestamp: Option<f32>) -> anyhow::Result<Vec<u8>> {
-    let part = match timestamp {
-        None => String::default(),
-        Some(timestamp) => format!("&time={}", timestamp),
-    };
+/// Whether `uuid` looks like one DeArrow hands out, as opposed to a mirror's garbage.
+fn valid_uuid(uuid: &str) -> bool {
+    !uuid.is_empty() && uuid.chars().all(|x| x.is_ascii_alphanumeric() || x == '-')
+}
 
-    let link = format!(
-        "https://dearrow-thumb.ajay.app/api/v1/getThumbnail?videoID={}{}",
-        vid_id, part
-    );
+/// The service we ask DeArrow about, as it names it.
+const SERVICE: &str = "YouTube";
 
-    Ok(reqwest::get(&link)
-        .await?
-        .bytes()
-        .await?
-        .into_iter()
-        .collect::<Vec<_>>())
-}
+impl BrandingResponse {
+    /// Makes sure the branding is for the YouTube video we asked about, as far as the response
+    /// says.
+    fn check_subject(&self, vid_id: &str) -> anyhow::Result<()> {
+        if let Some(ref service) = self.service
+            && !service.eq_ignore_ascii_case(SERVICE)
+        {
+            anyhow::bail!("got branding for service {service:?} instead of {SERVICE}");
+        }
+
+        if let Some(ref video_id) = self.video_id
+            && video_id != vid_id
+        {
+            anyhow::bail!("got branding for video {video_id:?} instead of {vid_id}");
+        }
 
-async fn get_branding(vid_id: &str) -> anyhow::Result<BrandingResponse> {
-    let req = reqwest::get(&format!(
-        "https://sponsor.ajay.app/api/branding?videoID={}",
-        vid_id
-    ))
-    .await?;
+        Ok(())
+    }
 
-    let res: BrandingResponse = req.json().await?;
+    /// Drops submissions with a missing or malformed UUID, and all but the first of those
+    /// sharing one. DeArrow itself never sends either, but a buggy mirror might.
+    fn validate(&mut self, vid_id: &str) {
+        let mut seen = HashSet::new();
+        let titles = self.titles.len();
+        self.titles
+            .retain(|x| valid_uuid(&x.uuid) && seen.insert(x.uuid.clone()));
 
-    Ok(res)
-}
+        let mut seen = HashSet::new();
+        let thumbnails = self.thumbnails.len();
+        self.thumbnails
+            .retain(|x| valid_uuid(&x.uuid) && seen.insert(x.uuid.clone()));
 
-struct Handler {
-    remove_embed: bool,
-    thumbnail_mode: ThumbnailMode,
-}
+        self.random_frame = Some(BrandingThumbnail {
+            timestamp: Some(self.random_time),
+            original: false,
+            votes: 0,
+            locked: false,
+            uuid: String::new(),
+            removed: false,
+            shadow_hidden: false,
+            random: true,
+        });
 
-#[async_trait]
-impl EventHandler for Handler {
-    async fn message(&self, ctx: Context, mut msg: Message) {
-        let bot_id = ctx.cache.current_user().id;
-        let mut msg_with_link = Box::new(msg.clone());
-        let mut bypass_checks = false;
-        if let Ok(x) = msg.mentions_me(&ctx.http).await
-            && x
-        {
-            // probably wants to force
-            if let Some(ref x) = msg.referenced_message {
-                // // make it so that if the reply is to a message by us we override all limits and redo it
-                if x.author.id == bot_id {
-                    //     // the reffed msg was ours.
-                    log::warn!("reffed msg was ours, but this path is not implemented yet.")
-                } else {
-                    log::info!(
-                        "overriding link to the message replied to by the message that @ed us"
-                    );
-                    msg_with_link = x.clone();
-                    bypass_checks = true;
-                }
-            }
+        let dropped = titles - self.titles.len() + thumbnails - self.thumbnails.len();
+        if dropped > 0 {
+            log::warn!(
+                "dropped {dropped} submission{} with a duplicate or malformed uuid for {vid_id}.",
+                if dropped == 1 { "" } else { "s" }
+            );
         }
+    }
 
-        let regex =
-            Regex::new(r#"(?:youtube(?:-nocookie)?\.com\/(?:[^\/\n\s]+\/\S+\/|(?:v|e(?:mbed)?)\/|\S*?[?&]v=)|youtu\.be\/)([a-zA-Z0-9_-]{11})"#)
-                .expect("failed to compile regex");
+    /// The video's length in seconds. `None` if DeArrow doesn't know it, which it also reports
+    /// as 0.
+    fn duration(&self) -> Option<f32> {
+        self.video_duration.filter(|x| *x > 0.0)
+    }
 
-        let link = msg_with_link.content_safe(ctx.cache);
+    /// The title to show, skipping submissions that were removed or hidden by moderators.
+    fn title(&self) -> Option<&BrandingTitle> {
+        self.visible_titles().next()
+    }
 
-        let Some(cap) = regex.captures(&link) else {
-            // log::warn!("regex did not capture");
-            return;
-        };
+    /// The best title by `selection`, skipping submissions that were removed or hidden by
+    /// moderators.
+    fn select_title(&self, selection: TitleSelection) -> Option<&BrandingTitle> {
+        match selection {
+            TitleSelection::First => self.title(),
+            TitleSelection::MostVotes => {
+                self.visible_titles()
+                    .fold(None, |best: Option<&BrandingTitle>, x| match best {
+                        Some(best) if best.votes >= x.votes => Some(best),
+                        _ => Some(x),
+                    })
+            }
+            TitleSelection::LockedFirst => self
+                .visible_titles()
+                .find(|x| x.locked)
+                .or_else(|| self.title()),
+        }
+    }
 
-        let Some(id) = cap.get(1) else {
-            log::warn!("link seemingly does not contain youtube id: {}", link);
-            return;
-        };
+    /// All titles that weren't removed or hidden by moderators, best first.
+    fn visible_titles(&self) -> impl Iterator<Item = &BrandingTitle> {
+        self.titles
+            .iter()
+            .filter(|x| !x.removed && !x.shadow_hidden)
+    }
 
-        let id = id.as_str().to_string();
+    /// The thumbnail to show, skipping submissions that were removed or hidden by moderators.
+    fn thumbnail(&self) -> Option<&BrandingThumbnail> {
+        self.visible_thumbnails().next()
+    }
 
-        log::info!("de-clickbaiting {id}!");
+    /// All thumbnails that weren't removed or hidden by moderators, best first.
+    fn visible_thumbnails(&self) -> impl Iterator<Item = &BrandingThumbnail> {
+        self.thumbnails
+            .iter()
+            .filter(|x| !x.removed && !x.shadow_hidden)
+    }
+}
 
-        let Ok(branding) = get_branding(&id)
-            .await
-            .map_err(|e| log::error!("failed to get branding! {e:#?}"))
-        else {
-            return;
-        };
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum ImageFormat {
+    Webp,
+    Png,
+    Jpeg,
+}
 
-        let Some(title) = branding.titles.first() else {
-            log::warn!("no brandings returned!");
-            return;
-        };
+impl ImageFormat {
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
 
-        if !bypass_checks && (!title.locked && title.votes < 2) {
-            log::warn!(
-                "untrusted branding (locked: {}, votes: {}). skipping.",
-                title.locked,
-                title.votes
-            );
-            return;
+        match mime.to_ascii_lowercase().as_str() {
+            "image/webp" => Some(ImageFormat::Webp),
+            "image/png" => Some(ImageFormat::Png),
+            "image/jpeg" | "image/jpg" => Some(ImageFormat::Jpeg),
+            _ => None,
         }
+    }
 
-        // if title.original {
-        //     log::warn!("title is just recapitalized, skipping.");
-        //     return;
-        // }
-
-        let override_thumb = msg.content.contains("DeArrow:force_thumbnail")
-            || msg_with_link.content.contains("DeArrow:force_thumbnail");
-
-        let thumb = if self.thumbnail_mode != ThumbnailMode::Disabled || override_thumb {
-            match branding.thumbnails.first() {
-                Some(thumbnail) => {
-                    if !override_thumb && (!thumbnail.locked && thumbnail.votes < 0) {
-                        log::warn!(
-                            "untrusted thumbnail (locked: {}, votes: {}). skipping.",
-                            title.locked,
-                            title.votes
-                        );
-                        None
-                    } else if !override_thumb
-                        && (!thumbnail.locked && self.thumbnail_mode == ThumbnailMode::OnlyLocked)
-                    {
-                        log::warn!("only locked thumbnails allowed.");
-
-                        None
-                    } else {
-                        get_thumbnail(&id, thumbnail.timestamp)
-                            .await
-                            .map_err(|e| log::error!("failed to retrieve thumbnail: {e:#?}"))
-                            .ok()
-                            .map(|x| (x, thumbnail.votes, thumbnail.locked))
-                    }
-                }
-                None => {
-                    log::warn!("no thumbnails returned!");
-                    None
-                }
-            }
+    /// Identifies the format from the file's magic bytes.
+    fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
         } else {
             None
-        };
+        }
+    }
 
-        let message = CreateMessage::new();
-
-        let thumb_present = thumb.is_some();
-
-        let message = match thumb {
-            Some((thumb, votes, locked)) => message
-                .add_file(CreateAttachment::bytes(thumb, "thumb.webp"))
-                .add_embed(
-                    CreateEmbed::new()
-                        .attachment("thumb.webp")
-                        .title(&title.title)
-                        .description(&format!(
-                            "Title: {} votes, is{}locked; Thumbnail: {} votes, is{}locked",
-                            title.votes,
-                            if title.locked { " " } else { " not " },
-                            votes,
-                            if locked { " " } else { " not " }
-                        ))
-                        .footer(CreateEmbedFooter::new(
-                            "De-Clickbait provided by DeArrow API.",
-                        )),
-                ),
-            None => message.add_embed(
-                CreateEmbed::new()
-                    .title(&title.title)
-                    .description(&format!(
-                        "Title: {} votes, is{}locked; Thumbnail: {}",
-                        title.votes,
-                        if title.locked { " " } else { " not " },
-                        match self.thumbnail_mode {
-                            ThumbnailMode::Disabled => "disabled by dev",
-                            ThumbnailMode::Enabled => "not found",
-                            ThumbnailMode::OnlyLocked => "disabled by dev (lock-only)",
-                        }
-                    ))
-                    .footer(CreateEmbedFooter::new(
-                        "De-Clickbait provided by DeArrow API.",
-                    )),
-            ),
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "webp",
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
         }
-        .reference_message(&msg);
+    }
+}
+
+#[derive(Clone)]
+struct Thumbnail {
+    bytes: Vec<u8>,
+    format: ImageFormat,
+}
+
+impl Thumbnail {
+    /// The file name for the `n`th thumbnail attached to a message.
+    fn filename(&self, n: usize) -> String {
+        format!("thumb_{n}.{}", self.format.extension())
+    }
 
-        log::info!("Successfully generated de-clickbaited embed for {id}!");
+    /// The thumbnail as the `n`th attachment, described by `alt_text` for screen readers.
+    fn into_attachment(self, n: usize, alt_text: Option<&str>) -> CreateAttachment {
+        let filename = self.filename(n);
+        let attachment = CreateAttachment::bytes(self.bytes, filename);
 
-        if let Err(e) = msg.channel_id.send_message(&ctx.http, message).await {
-            log::error!("could not send message: {e:#?}");
+        match alt_text {
+            Some(alt_text) => attachment.description(alt_text),
+            None => attachment,
         }
+    }
+}
 
-        if self.thumbnail_mode != ThumbnailMode::Disabled && thumb_present && self.remove_embed {
-            if msg.embeds.len() == 0 {
-                log::info!("waiting for discord to embed the video!");
-                let msg_id = msg.id.clone();
+#[tracing::instrument(skip_all, fields(video_id = vid_id))]
+async fn get_thumbnail(
+    client: &reqwest::Client,
+    base: &str,
+    vid_id: &str,
+    timestamp: Option<f32>,
+) -> Result<Thumbnail, DeArrowError> {
+    let res = fetch_thumbnail_image(client, base, vid_id, timestamp).await;
 
-                let mut message_updates =
-                    serenity::collector::collect(&ctx.shard, move |ev| match ev {
-                        Event::MessageUpdate(x) if x.id == msg_id => Some(()),
-                        _ => None,
-                    });
+    #[cfg(feature = "metrics")]
+    metrics::THUMBNAIL.record(&res);
 
-                let _ = tokio::time::timeout(
-                    std::time::Duration::from_millis(5000),
-                    message_updates.next(),
-                )
-                .await;
-            }
+    res
+}
+
+async fn fetch_thumbnail_image(
+    client: &reqwest::Client,
+    base: &str,
+    vid_id: &str,
+    timestamp: Option<f32>,
+) -> Result<Thumbnail, DeArrowError> {
+    let part = match timestamp {
+        None => String::default(),
+        Some(timestamp) => format!("&time={}", timestamp),
+    };
+
+    let link = format!(
+        "{}/api/v1/getThumbnail?videoID={}{}",
+        base.trim_end_matches('/'),
+        vid_id,
+        part
+    );
 
-            log::info!("editing message to remove original embed!");
+    let res = client.get(&link).send().await?.error_for_status()?;
 
-            if let Err(e) = msg
-                .edit(&ctx.http, EditMessage::new().suppress_embeds(true))
-                .await
-            {
-                log::error!("unable to edit sent message: {e:#?}");
-            }
+    if !content_type(&res).contains("json") {
+        return read_thumbnail(res).await;
+    }
+
+    // the image may come wrapped, rather than as the body itself.
+    match res.json::<ThumbnailEnvelope>().await?.source()? {
+        ThumbnailSource::Data(bytes) => {
+            let Some(format) = ImageFormat::sniff(&bytes) else {
+                return Err(anyhow::anyhow!("thumbnail data is not a known image format").into());
+            };
+
+            Ok(Thumbnail { bytes, format })
+        }
+        ThumbnailSource::Url(url) => {
+            let url = reqwest::Url::parse(&link)
+                .and_then(|x| x.join(&url))
+                .map_err(anyhow::Error::from)?;
+            read_thumbnail(client.get(url).send().await?.error_for_status()?).await
         }
     }
 }
 
-#[derive(Parser)]
-struct Args {
-    #[arg(long, env)]
-    /// The discord token for the bot
-    token: String,
+fn content_type(res: &reqwest::Response) -> String {
+    res.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
 
-    #[arg(long, env, default_value_t = ThumbnailMode::OnlyLocked)]
-    /// The Thumbnail Mode. Indicates whether or not thumbnails should be embedded. "Locked" here refers to a crowd-sourced thumbnail having reached consensus status.
-    thumbnail_mode: ThumbnailMode,
+/// The thumbnail making up the body of `res`.
+async fn read_thumbnail(res: reqwest::Response) -> Result<Thumbnail, DeArrowError> {
+    let content_type = content_type(&res);
 
-    #[arg(long, env)]
-    /// Whether to remove the original embed from the sender.
-    remove_embed: bool,
-}
+    let Some(format) = ImageFormat::from_content_type(&content_type) else {
+        return Err(
+            anyhow::anyhow!("thumbnail has unexpected content type {content_type:?}").into(),
+        );
+    };
 
-#[tokio::main]
-async fn main() {
-    colog::default_builder()
-        .default_format()
-        .filter(Some("serenity"), log::LevelFilter::Warn)
-        .filter(Some("serenity"), log::LevelFilter::Warn)
-        .filter(Some("serenity"), log::LevelFilter::Warn)
-        .filter(Some("tracing::span"), log::LevelFilter::Warn)
-        .filter_level(log::LevelFilter::Info)
-        .init();
+    let bytes = res.bytes().await?.to_vec();
 
-    #[cfg(debug_assertions)]
-    {
-        let token = include_str!("token");
-        // wtf lmao why is this unsafe
-        unsafe { std::env::set_var("TOKEN", token) };
+    if ImageFormat::sniff(&bytes) != Some(format) {
+        return Err(anyhow::anyhow!(
+            "thumbnail content does not match its content type {content_type:?}"
+        )
+        .into());
     }
 
-    let args = Args::parse();
+    Ok(Thumbnail { bytes, format })
+}
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+/// A JSON reply from the thumbnail endpoint, pointing at the image or carrying it.
+#[derive(serde::Deserialize, Debug)]
+struct ThumbnailEnvelope {
+    #[serde(default, alias = "imageUrl", alias = "image_url")]
+    url: Option<String>,
+    /// Base64, possibly as a `data:` URL.
+    #[serde(default, alias = "image", alias = "base64")]
+    data: Option<String>,
+}
 
-    log::info!("creating client");
+enum ThumbnailSource {
+    Url(String),
+    Data(Vec<u8>),
+}
 
-    let mut client = Client::builder(&args.token, intents)
-        .event_handler(Handler {
-            remove_embed: args.remove_embed,
-            thumbnail_mode: args.thumbnail_mode,
-        })
-        .await
+impl ThumbnailEnvelope {
+    fn source(self) -> anyhow::Result<ThumbnailSource> {
+        use base64::Engine;
+
+        if let Some(data) = self.data {
+            let encoded = match data.split_once(";base64,") {
+                Some((_, encoded)) if data.starts_with("data:") => encoded,
+                _ => &data,
+            };
+
+            return Ok(ThumbnailSource::Data(
+                base64::engine::general_purpose::STANDARD.decode(encoded.trim())?,
+            ));
+        }
+
+        match self.url {
+            Some(url) => Ok(ThumbnailSource::Url(url)),
+            None => anyhow::bail!("thumbnail response has neither an image nor a link to one"),
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(video_id = vid_id))]
+async fn get_branding(
+    client: &reqwest::Client,
+    base: &str,
+    vid_id: &str,
+) -> Result<BrandingResponse, DeArrowError> {
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+
+    let res = fetch_branding(client, base, vid_id).await;
+
+    #[cfg(feature = "metrics")]
+    {
+        metrics::BRANDING.record(&res);
+        metrics::BRANDING_LATENCY.record(started.elapsed());
+    }
+
+    res
+}
+
+async fn fetch_branding(
+    client: &reqwest::Client,
+    base: &str,
+    vid_id: &str,
+) -> Result<BrandingResponse, DeArrowError> {
+    let req = client
+        .get(format!(
+            "{}/api/branding?videoID={}&service={SERVICE}",
+            base.trim_end_matches('/'),
+            vid_id
+        ))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let mut res: BrandingResponse = req.json().await?;
+    res.check_subject(vid_id)?;
+    res.validate(vid_id);
+    res.fetched_at = Some(std::time::SystemTime::now());
+
+    Ok(res)
+}
+
+static MENTION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:@[!&]?|#)\d+>").expect("failed to compile regex"));
+
+/// The message's content with mentions made harmless. Uses the cache to resolve them to names
+/// where it can, or just strips them if the cache is disabled or doesn't know the guild yet.
+fn safe_content(cache: &serenity::cache::Cache, msg: &Message) -> String {
+    let cached = msg
+        .guild_id
+        .is_none_or(|guild_id| cache.guild(guild_id).is_some());
+
+    if cached {
+        return msg.content_safe(cache);
+    }
+
+    MENTION_REGEX
+        .replace_all(&msg.content, "")
+        .replace("@everyone", "@\u{200B}everyone")
+        .replace("@here", "@\u{200B}here")
+}
+
+fn describe_thumbnail(thumbnail: &BrandingThumbnail) -> String {
+    if thumbnail.random {
+        return format!(
+            "random frame at {}s",
+            thumbnail.timestamp.unwrap_or_default()
+        );
+    }
+
+    format!(
+        "{}{} votes, is{}locked",
+        if thumbnail.original {
+            "the original, "
+        } else {
+            ""
+        },
+        thumbnail.votes,
+        if thumbnail.locked { " " } else { " not " }
+    )
+}
+
+/// Waits up to `timeout` for the next update to `msg`, which is how Discord delivers the embeds
+/// it generates for links. Returns the embeds of that update.
+async fn wait_for_embeds(
+    ctx: &Context,
+    msg: &Message,
+    timeout: std::time::Duration,
+) -> Option<Vec<Embed>> {
+    let msg_id = msg.id;
+
+    let mut message_updates = serenity::collector::collect(&ctx.shard, move |ev| match ev {
+        Event::MessageUpdate(x) if x.id == msg_id => Some(x.embeds.clone().unwrap_or_default()),
+        _ => None,
+    });
+
+    tokio::time::timeout(timeout, message_updates.next())
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Waits until Discord has embedded the link in `msg`, so suppressing its embeds doesn't race
+/// the embed we mean to replace:
+///
+/// 1. start listening for updates to `msg`, so one arriving while we look isn't missed.
+/// 2. check whether it has embeds already, re-fetching it since they may have arrived while we
+///    were busy replying.
+/// 3. if it has none, wait up to `timeout` for them. if it does, wait up to `settle` for any
+///    further update, in case they're still changing.
+///
+/// Returns whether `msg` has embeds by now.
+async fn wait_until_embedded(
+    ctx: &Context,
+    msg: &Message,
+    timeout: std::time::Duration,
+    settle: std::time::Duration,
+) -> bool {
+    let msg_id = msg.id;
+
+    let mut message_updates = serenity::collector::collect(&ctx.shard, move |ev| match ev {
+        Event::MessageUpdate(x) if x.id == msg_id => {
+            Some(x.embeds.as_ref().is_some_and(|x| !x.is_empty()))
+        }
+        _ => None,
+    });
+
+    let embedded = !msg.embeds.is_empty()
+        || ctx
+            .http
+            .get_message(msg.channel_id, msg.id)
+            .await
+            .is_ok_and(|x| !x.embeds.is_empty());
+
+    if !embedded {
+        log::info!("waiting for discord to embed the video!");
+    }
+
+    let wait = embed_wait(embedded, timeout, settle);
+
+    if wait.is_zero() {
+        return embedded;
+    }
+
+    let update = tokio::time::timeout(wait, message_updates.next())
+        .await
+        .ok()
+        .flatten();
+
+    embedded_after(embedded, update)
+}
+
+/// How long [`wait_until_embedded`] waits for an update, given whether `msg` had embeds already.
+fn embed_wait(
+    embedded: bool,
+    timeout: std::time::Duration,
+    settle: std::time::Duration,
+) -> std::time::Duration {
+    if embedded { settle } else { timeout }
+}
+
+/// Whether `msg` has embeds, given whether it had them already and the update that came in the
+/// meantime, if any, with whether that one has embeds.
+fn embedded_after(embedded: bool, update: Option<bool>) -> bool {
+    embedded || update.unwrap_or(false)
+}
+
+/// Cooldowns kept in the `name` tree of `store` across restarts, or only in memory without one.
+fn persisted_cooldowns<K: Eq + std::hash::Hash + Clone + state::StoreKey>(
+    store: Option<&state::Store>,
+    name: &str,
+    window: std::time::Duration,
+    capacity: usize,
+) -> state::Cooldowns<K> {
+    match store {
+        Some(store) => state::Cooldowns::persisted(
+            window,
+            capacity,
+            store
+                .timestamps_expiring(name, window)
+                .expect("failed to open persisted cooldowns"),
+        ),
+        None => state::Cooldowns::new(window, capacity),
+    }
+}
+
+/// Resolves on SIGINT, or SIGTERM where there is one.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => log::warn!("could not listen for SIGTERM: {e}"),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("could not listen for SIGINT, only killing the bot stops it now: {e}");
+        std::future::pending::<()>().await;
+    }
+}
+
+/// A check that turns a message, or one of the videos it links, away before it's replied to.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Gate {
+    /// A DM, without --allow-dm.
+    DmsDisabled,
+    /// Outside the allowed guilds or channels.
+    NotAllowed,
+    /// Discord refused a reply in the channel recently.
+    DeniedChannel,
+    /// The author opted out of replies.
+    OptedOut,
+    Maintenance,
+    /// The author got a reply in the channel recently.
+    UserCooldown,
+    /// We lack these to reply in the channel.
+    MissingPermissions(Permissions),
+    /// The video got a reply recently.
+    VideoCooldown,
+    /// The video got a reply in the channel recently.
+    ChannelVideoCooldown,
+}
+
+impl std::fmt::Display for Gate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Gate::DmsDisabled => write!(f, "replies in DMs are disabled"),
+            Gate::NotAllowed => write!(f, "the guild or channel is not allowlisted"),
+            Gate::DeniedChannel => write!(f, "discord refused a reply in this channel recently"),
+            Gate::OptedOut => write!(f, "the author opted out of replies"),
+            Gate::Maintenance => write!(f, "the bot is in maintenance mode"),
+            Gate::UserCooldown => write!(f, "the author got a reply in this channel recently"),
+            Gate::MissingPermissions(missing) => write!(f, "missing {missing} to reply here"),
+            Gate::VideoCooldown => write!(f, "the video was de-clickbaited recently elsewhere"),
+            Gate::ChannelVideoCooldown => {
+                write!(f, "the video was de-clickbaited recently in this channel")
+            }
+        }
+    }
+}
+
+struct Handler {
+    remove_embed: bool,
+    /// Channels in which the original embed is removed even without `remove_embed`.
+    suppress_embed_channels: HashSet<ChannelId>,
+    /// Where to reply, everywhere if empty.
+    allowed_channels: HashSet<ChannelId>,
+    allowed_guilds: HashSet<GuildId>,
+    policy: decision::Policy,
+    allow_dm: bool,
+    /// Link to the video's page in a DeArrow submission browser, with `{video_id}` substituted.
+    dearrow_page: Option<String>,
+    /// Describes attached thumbnails, with `{title}` substituted. No description if `None`.
+    alt_text_format: Option<String>,
+    /// Thumbnails larger than this are recompressed to fit.
+    max_thumbnail_bytes: usize,
+    /// Shown in the footer of the replies, `None` if left out.
+    embed_footer: Option<String>,
+    embed_color: Option<Colour>,
+    show_sharer: bool,
+    trust_polls: bool,
+    trust_poll_margin: isize,
+    clips: Option<youtube::ClipResolver>,
+    thumbnail_permits: Arc<tokio::sync::Semaphore>,
+    thumbnail_retry_delay: Option<std::time::Duration>,
+    background_thumbnails: bool,
+    youtube_thumbnail_fallback: bool,
+    event_webhook: Option<events::EventWebhook>,
+    plain_text_fallback: bool,
+    emojis: emoji::GuildEmojis,
+    /// Channels we recently warned about missing the Embed Links permission in.
+    embed_warnings: Arc<state::Cooldowns<ChannelId>>,
+    /// Channels Discord recently refused our replies in.
+    denied_channels: Arc<state::Cooldowns<ChannelId>>,
+    guild_command_limits: Arc<state::RateLimiter<GuildId>>,
+    user_command_limits: Arc<state::RateLimiter<UserId>>,
+    replies: Arc<state::ReplyLog>,
+    /// When each video was last replied to, across all channels.
+    video_cooldowns: Option<Arc<state::Cooldowns<String>>>,
+    /// When each video was last replied to in each channel.
+    channel_video_cooldowns: Option<Arc<state::Cooldowns<(ChannelId, String)>>>,
+    /// When each user last got a reply in each channel.
+    user_cooldowns: Option<Arc<state::Cooldowns<(ChannelId, UserId)>>>,
+    opt_outs: optout::OptOuts,
+    health: Arc<health::Health>,
+    /// Guilds we were removed from, until their grace period is up.
+    departures: guilds::Departures,
+    /// The videos each message linked when we last looked at it, so edits only get new ones
+    /// processed.
+    seen_links: Arc<state::Cooldowns<(MessageId, String)>>,
+    trust_tiers: trust::TrustTiers,
+    edits: Arc<edits::EditScheduler>,
+    show_original_when_empty: bool,
+    embed_machine_tag: bool,
+    output_style: OutputStyle,
+    reply_mode: reply::ReplyMode,
+    channel_reply_modes: HashMap<ChannelId, reply::ReplyMode>,
+    embed_limit: reply::EmbedLimit,
+    /// How many bytes of a message are looked through for links, and what happens beyond that.
+    max_scan_len: usize,
+    oversized_content: OversizedContent,
+    /// Slash commands turned off per guild.
+    disabled_commands: HashSet<(GuildId, String)>,
+    /// Users allowed to use the owner commands.
+    owners: HashSet<UserId>,
+    show_channel: bool,
+    /// Whether moderators can react with the retrigger emoji to have a message's link
+    /// processed again.
+    retrigger_reactions: bool,
+    /// How long to wait for Discord to embed a link it hasn't yet.
+    embed_wait: std::time::Duration,
+    /// How long to wait for embeds that are already there to stop changing before suppressing them.
+    embed_settle: std::time::Duration,
+    maintenance: maintenance::Maintenance,
+    activity: Option<Arc<summary::Activity>>,
+    /// Shared by all requests to DeArrow and YouTube, so connections get reused.
+    client: reqwest::Client,
+    branding_api_base: String,
+    thumbnail_api_base: String,
+    /// How often a request to DeArrow is tried before giving up on it.
+    retry_attempts: u32,
+    responses: Option<Arc<cache::ResponseCache>>,
+    disk_thumbnails: Option<Arc<cache::DiskThumbnails>>,
+    verify_video_exists: bool,
+    show_fetch_time: bool,
+    show_original_title: bool,
+    /// Takes turns between guilds when more messages come in than we process at once.
+    turns: Arc<schedule::FairGate>,
+    /// Whether the slash commands were registered with Discord already.
+    commands_registered: AtomicBool,
+    #[cfg(feature = "metrics")]
+    timings: Arc<metrics::HandlerTimings>,
+}
+
+impl Handler {
+    /// [`get_branding`], unless it was fetched within `--response-cache-ttl`.
+    async fn branding(&self, vid_id: &str) -> Result<BrandingResponse, DeArrowError> {
+        let cached = self.responses.as_ref().map(|x| x.branding(vid_id));
+
+        #[cfg(feature = "metrics")]
+        if let Some(ref cached) = cached {
+            metrics::BRANDING_CACHE.record(cached.is_some());
+        }
+
+        if let Some(branding) = cached.flatten() {
+            log::debug!("using cached branding for {vid_id}.");
+            return Ok(branding);
+        }
+
+        let branding = retry_transient(self.retry_attempts, "fetching branding", || {
+            get_branding(&self.client, &self.branding_api_base, vid_id)
+        })
+        .await?;
+
+        if let Some(ref responses) = self.responses {
+            responses.insert_branding(vid_id, branding.clone());
+        }
+
+        Ok(branding)
+    }
+
+    /// [`get_thumbnail`], but limited to `--max-thumbnail-concurrency` downloads at once, and
+    /// skipped if it was downloaded within `--response-cache-ttl`.
+    async fn fetch_thumbnail(
+        &self,
+        vid_id: &str,
+        timestamp: Option<f32>,
+    ) -> Result<Thumbnail, DeArrowError> {
+        if let Some(thumb) = self
+            .responses
+            .as_ref()
+            .and_then(|x| x.thumbnail(vid_id, timestamp))
+        {
+            log::debug!("using cached thumbnail for {vid_id}.");
+
+            #[cfg(feature = "metrics")]
+            metrics::THUMBNAIL_CACHE.record(true);
+
+            return Ok(thumb);
+        }
+
+        let on_disk = match self.disk_thumbnails {
+            Some(ref disk) => disk.get(vid_id, timestamp).await,
+            None => None,
+        };
+
+        #[cfg(feature = "metrics")]
+        if self.responses.is_some() || self.disk_thumbnails.is_some() {
+            metrics::THUMBNAIL_CACHE.record(on_disk.is_some());
+        }
+
+        let thumb = match on_disk {
+            Some(thumb) => {
+                log::debug!("using thumbnail for {vid_id} from disk.");
+                thumb
+            }
+            None => {
+                let thumb = self.download_thumbnail(vid_id, timestamp).await?;
+
+                if let Some(ref disk) = self.disk_thumbnails {
+                    disk.insert(vid_id, timestamp, &thumb).await;
+                }
+
+                thumb
+            }
+        };
+
+        if let Some(ref responses) = self.responses {
+            responses.insert_thumbnail(vid_id, timestamp, thumb.clone());
+        }
+
+        Ok(thumb)
+    }
+
+    /// The thumbnail from DeArrow itself, bypassing the caches, ready to attach.
+    async fn download_thumbnail(
+        &self,
+        vid_id: &str,
+        timestamp: Option<f32>,
+    ) -> Result<Thumbnail, DeArrowError> {
+        let thumb = {
+            // the semaphore is never closed.
+            let _permit = self.thumbnail_permits.acquire().await.ok();
+            retry_transient(self.retry_attempts, "fetching thumbnail", || {
+                get_thumbnail(&self.client, &self.thumbnail_api_base, vid_id, timestamp)
+            })
+            .await?
+        };
+
+        recompress::fit(thumb, self.max_thumbnail_bytes).await
+    }
+
+    /// [`Self::fetch_thumbnail`], retrying once after `--thumbnail-retry-delay-ms` if set.
+    async fn fetch_thumbnail_retrying(
+        &self,
+        vid_id: &str,
+        timestamp: Option<f32>,
+    ) -> Result<Thumbnail, DeArrowError> {
+        match (
+            self.fetch_thumbnail(vid_id, timestamp).await,
+            self.thumbnail_retry_delay,
+        ) {
+            (Err(e), Some(delay)) if !matches!(e, DeArrowError::TooLarge) => {
+                log::warn!("failed to retrieve thumbnail, retrying in {delay:?}: {e:#?}");
+                tokio::time::sleep(delay).await;
+                self.fetch_thumbnail(vid_id, timestamp).await
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Tries fetching the thumbnail again after a while, and edits it into `reply` once it
+    /// arrives.
+    async fn add_thumbnail_later(
+        &self,
+        ctx: &Context,
+        mut reply: Message,
+        vid_id: &str,
+        thumbnail: &BrandingThumbnail,
+        embed: CreateEmbed,
+        alt_text: Option<String>,
+    ) {
+        let delay = self
+            .thumbnail_retry_delay
+            .unwrap_or(std::time::Duration::from_secs(5));
+        tokio::time::sleep(delay).await;
+
+        let thumb = match self
+            .fetch_thumbnail_retrying(vid_id, thumbnail.timestamp)
+            .await
+        {
+            Ok(thumb) => thumb,
+            Err(e) => {
+                log::warn!("giving up on the thumbnail for {vid_id}: {e:#?}");
+                return;
+            }
+        };
+
+        let edit = EditMessage::new()
+            .embed(embed.attachment(thumb.filename(0)))
+            .new_attachment(thumb.into_attachment(0, alt_text.as_deref()));
+
+        match self.edits.edit(&ctx.http, &mut reply, edit).await {
+            Ok(()) => log::info!("added the late thumbnail for {vid_id}."),
+            Err(e) => log::error!("unable to add the late thumbnail: {e:#?}"),
+        }
+    }
+
+    /// An embed in `--embed-color`, if set.
+    fn new_embed(&self) -> CreateEmbed {
+        match self.embed_color {
+            Some(color) => CreateEmbed::new().colour(color),
+            None => CreateEmbed::new(),
+        }
+    }
+
+    fn build_embed(
+        &self,
+        vid_id: &str,
+        branding: &BrandingResponse,
+        title: &BrandingTitle,
+        thumbnail_status: &str,
+        sharer: Option<&str>,
+    ) -> CreateEmbed {
+        let tier = self.trust_tiers.tier(title.votes, title.locked);
+
+        let footer = [
+            sharer.map(|x| format!("Shared by {x}")),
+            self.embed_footer.clone(),
+            // lets downstream tooling tell which submission was shown without parsing the rest.
+            self.embed_machine_tag
+                .then(|| format!("dearrow:{vid_id}:{}", title.uuid)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" · ");
+
+        let mut embed = self.new_embed().title(&title.title).description(format!(
+            "**{}**\nTitle: {} votes, is{}locked; Thumbnail: {}",
+            tier,
+            title.votes,
+            if title.locked { " " } else { " not " },
+            thumbnail_status
+        ));
+
+        if !footer.is_empty() {
+            embed = embed.footer(CreateEmbedFooter::new(footer));
+        }
+
+        if self.show_fetch_time
+            && let Some(fetched_at) = branding.fetched_at
+            && let Ok(timestamp) = Timestamp::from_unix_timestamp(
+                fetched_at
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as i64,
+            )
+        {
+            embed = embed.timestamp(timestamp);
+        }
+
+        if let Some(duration) = branding.duration() {
+            embed = embed.field("Length", youtube::format_duration(duration), true);
+        }
+
+        if let Some(ref page) = self.dearrow_page {
+            embed = embed.field(
+                "DeArrow",
+                format!(
+                    "[See all submissions]({})",
+                    page.replace("{video_id}", vid_id)
+                ),
+                false,
+            );
+        }
+
+        embed
+    }
+
+    /// An embed with the video's original title, for videos nobody submitted a title for yet.
+    /// `None` if it can't be looked up.
+    async fn original_part(
+        &self,
+        ctx: &Context,
+        msg: &Message,
+        vid_id: &str,
+    ) -> Option<reply::ReplyPart> {
+        let can_embed = msg.guild_id.is_none_or(|guild_id| {
+            permissions::in_channel(&ctx.cache, guild_id, msg.channel_id)
+                .is_none_or(|x| x.contains(Permissions::EMBED_LINKS))
+        });
+
+        if !can_embed {
+            return None;
+        }
+
+        let title = match youtube::oembed(&self.client, vid_id).await {
+            Ok(oembed) => oembed.title,
+            Err(e) => {
+                log::warn!("failed to look up the original title of {vid_id}: {e:#?}");
+                return None;
+            }
+        };
+
+        let mut embed = self
+            .new_embed()
+            .title(title)
+            .description("No DeArrow data yet — original title shown.")
+            .footer(CreateEmbedFooter::new("Title provided by YouTube."));
+
+        if let Some(ref page) = self.dearrow_page {
+            embed = embed.field(
+                "DeArrow",
+                format!("[Submit a title]({})", page.replace("{video_id}", vid_id)),
+                false,
+            );
+        }
+
+        Some(reply::ReplyPart {
+            embed,
+            thumbnail: None,
+            alt_text: None,
+            buttons: self.buttons(vid_id),
+        })
+    }
+
+    /// An embed with just DeArrow's thumbnail, for videos that got one submitted but no title
+    /// yet. `None` if it can't be downloaded.
+    async fn thumbnail_only_part(
+        &self,
+        ctx: &Context,
+        msg: &Message,
+        vid_id: &str,
+        thumbnail: &BrandingThumbnail,
+    ) -> Option<reply::ReplyPart> {
+        let can_embed = msg.guild_id.is_none_or(|guild_id| {
+            permissions::in_channel(&ctx.cache, guild_id, msg.channel_id)
+                .is_none_or(|x| x.contains(Permissions::EMBED_LINKS))
+        });
+
+        if !can_embed {
+            return None;
+        }
+
+        let thumb = match self
+            .fetch_thumbnail_retrying(vid_id, thumbnail.timestamp)
+            .await
+        {
+            Ok(thumb) => thumb,
+            Err(e) => {
+                log::warn!("failed to retrieve the thumbnail for {vid_id}: {e:#?}");
+                return None;
+            }
+        };
+
+        // the reply reads fine without, if youtube doesn't tell us.
+        let title = youtube::oembed(&self.client, vid_id)
+            .await
+            .map_err(|e| log::warn!("failed to look up the original title of {vid_id}: {e:#?}"))
+            .map(|x| x.title)
+            .unwrap_or_else(|_| "No title change available".to_string());
+
+        let mut embed = self.new_embed().title(&title).description(format!(
+            "No title change available; Thumbnail: {}",
+            describe_thumbnail(thumbnail)
+        ));
+
+        if let Some(ref footer) = self.embed_footer {
+            embed = embed.footer(CreateEmbedFooter::new(footer));
+        }
+
+        if let Some(ref page) = self.dearrow_page {
+            embed = embed.field(
+                "DeArrow",
+                format!("[Submit a title]({})", page.replace("{video_id}", vid_id)),
+                false,
+            );
+        }
+
+        Some(reply::ReplyPart {
+            embed,
+            thumbnail: Some(thumb),
+            alt_text: self.thumbnail_alt_text(&title),
+            buttons: self.buttons(vid_id),
+        })
+    }
+
+    /// Stops replying in a channel for a while if Discord refused a reply there for lack of
+    /// permissions, instead of failing the same way for every link posted.
+    fn note_denied(&self, channel_id: ChannelId, sent: &reply::Sent) {
+        if !sent.denied || self.denied_channels.is_cooling_down(&channel_id) {
+            return;
+        }
+
+        log::warn!(
+            "missing access or permissions to reply in channel {channel_id}, not trying again there for {} minutes.",
+            DENIED_CHANNEL_COOLDOWN.as_secs() / 60
+        );
+        self.denied_channels.start(channel_id);
+    }
+
+    /// Drops what we kept about a guild we were removed from, and its `channels`. The
+    /// allowlists and per-guild settings are from the command line and stay, as do opt-outs,
+    /// which are up to the user wherever they are.
+    fn forget_guild(&self, guild_id: GuildId, channels: &HashSet<ChannelId>) {
+        let mut forgotten = self.guild_command_limits.forget(|x| *x == guild_id);
+        forgotten += self.denied_channels.forget(|x| channels.contains(x));
+        forgotten += self.embed_warnings.forget(|x| channels.contains(x));
+
+        if let Some(ref cooldowns) = self.channel_video_cooldowns {
+            forgotten += cooldowns.forget(|(x, _)| channels.contains(x));
+        }
+
+        if let Some(ref cooldowns) = self.user_cooldowns {
+            forgotten += cooldowns.forget(|(x, _)| channels.contains(x));
+        }
+
+        log::info!("forgot {forgotten} entries about guild {guild_id}.");
+    }
+
+    /// Whether `msg` was posted where the allowlists let us reply. DMs are up to --allow-dm,
+    /// and threads count as part of their parent channel.
+    fn is_allowed(
+        &self,
+        cache: &serenity::cache::Cache,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+    ) -> bool {
+        let Some(guild_id) = guild_id else {
+            return true;
+        };
+
+        if !self.allowed_guilds.is_empty() && !self.allowed_guilds.contains(&guild_id) {
+            return false;
+        }
+
+        if self.allowed_channels.is_empty() || self.allowed_channels.contains(&channel_id) {
+            return true;
+        }
+
+        cache
+            .guild(guild_id)
+            .and_then(|x| x.threads.iter().find(|x| x.id == channel_id)?.parent_id)
+            .is_some_and(|parent_id| self.allowed_channels.contains(&parent_id))
+    }
+
+    /// The first gate turning away a message by `author` in `channel_id`, whatever videos it
+    /// links. Only worth checking once it's known to link any. `retrigger`s don't count
+    /// against the author's cooldown.
+    fn message_gate(
+        &self,
+        cache: &serenity::cache::Cache,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        author: UserId,
+        retrigger: bool,
+    ) -> Result<(), Gate> {
+        if guild_id.is_none() && !self.allow_dm {
+            return Err(Gate::DmsDisabled);
+        }
+
+        if !self.is_allowed(cache, guild_id, channel_id) {
+            return Err(Gate::NotAllowed);
+        }
+
+        if self.denied_channels.is_cooling_down(&channel_id) {
+            return Err(Gate::DeniedChannel);
+        }
+
+        if self.opt_outs.contains(author) {
+            return Err(Gate::OptedOut);
+        }
+
+        if self.maintenance.is_active() {
+            return Err(Gate::Maintenance);
+        }
+
+        if !retrigger
+            && let Some(ref cooldowns) = self.user_cooldowns
+            && cooldowns.is_cooling_down(&(channel_id, author))
+        {
+            return Err(Gate::UserCooldown);
+        }
+
+        // no use asking DeArrow about videos we couldn't reply with anyway.
+        if let Some(guild_id) = guild_id
+            && let Some(missing) = permissions::missing_to_reply(
+                cache,
+                guild_id,
+                channel_id,
+                !self.plain_text_fallback,
+            )
+            && !missing.is_empty()
+        {
+            return Err(Gate::MissingPermissions(missing));
+        }
+
+        Ok(())
+    }
+
+    /// The first gate turning away a reply with `vid_id` in `channel_id`. `forced` replies
+    /// skip them.
+    fn video_gate(&self, channel_id: ChannelId, vid_id: &str, forced: bool) -> Result<(), Gate> {
+        if forced {
+            return Ok(());
+        }
+
+        if let Some(ref cooldowns) = self.video_cooldowns
+            && cooldowns.is_cooling_down(&vid_id.to_string())
+        {
+            return Err(Gate::VideoCooldown);
+        }
+
+        if let Some(ref cooldowns) = self.channel_video_cooldowns
+            && cooldowns.is_cooling_down(&(channel_id, vid_id.to_string()))
+        {
+            return Err(Gate::ChannelVideoCooldown);
+        }
+
+        Ok(())
+    }
+
+    /// Whether `vid_id` is still up on YouTube, as far as `--verify-video-exists` goes. Better
+    /// a reply to a dead link than none to a live one, so it is if we can't tell.
+    async fn video_exists(&self, vid_id: &str) -> bool {
+        if !self.verify_video_exists {
+            return true;
+        }
+
+        match youtube::exists(&self.client, vid_id).await {
+            Ok(exists) => exists,
+            Err(e) => {
+                log::warn!(video_id = vid_id; "could not check whether {vid_id} still exists: {e:#?}");
+                true
+            }
+        }
+    }
+
+    /// The message a reply to `msg` should point back at, if any.
+    fn reference<'a>(&self, msg: &'a Message) -> Option<&'a Message> {
+        let mode = self
+            .channel_reply_modes
+            .get(&msg.channel_id)
+            .copied()
+            .unwrap_or(self.reply_mode);
+
+        reply::reference(msg, mode)
+    }
+
+    /// The screen reader description for a thumbnail shown with `title`.
+    fn thumbnail_alt_text(&self, title: &str) -> Option<String> {
+        let alt_text = self.alt_text_format.as_ref()?.replace("{title}", title);
+
+        Some(alt_text.chars().take(MAX_ALT_TEXT_LEN).collect())
+    }
+
+    /// Link buttons to go along with the embed for `vid_id`, for `--output-style components`.
+    fn buttons(&self, vid_id: &str) -> Vec<CreateButton> {
+        if self.output_style != OutputStyle::Components {
+            return Vec::new();
+        }
+
+        let mut buttons = vec![
+            CreateButton::new_link(format!("https://www.youtube.com/watch?v={vid_id}"))
+                .label("Watch on YouTube"),
+        ];
+
+        if let Some(ref page) = self.dearrow_page {
+            buttons.push(
+                CreateButton::new_link(page.replace("{video_id}", vid_id)).label("Vote on DeArrow"),
+            );
+        }
+
+        buttons
+    }
+
+    /// Bookkeeping after a reply to `msg` about `vid_id` was sent.
+    fn record_reply(
+        &self,
+        msg: &Message,
+        vid_id: &str,
+        title: &BrandingTitle,
+        thumbnail: Option<&BrandingThumbnail>,
+    ) {
+        log::info!(
+            video_id = vid_id;
+            "replied to {vid_id} with title {} (votes: {}, locked: {}) and {}.",
+            title.uuid,
+            title.votes,
+            title.locked,
+            match thumbnail {
+                Some(x) if x.random => "a random frame".to_string(),
+                Some(x) => format!(
+                    "thumbnail {} (votes: {}, locked: {})",
+                    x.uuid, x.votes, x.locked
+                ),
+                None => "no thumbnail".to_string(),
+            }
+        );
+
+        self.replies.insert(msg.id, vid_id);
+
+        #[cfg(feature = "metrics")]
+        metrics::DECLICKBAITS.increment();
+
+        if let Some(ref cooldowns) = self.video_cooldowns {
+            cooldowns.start(vid_id.to_string());
+        }
+
+        if let Some(ref cooldowns) = self.channel_video_cooldowns {
+            cooldowns.start((msg.channel_id, vid_id.to_string()));
+        }
+
+        if let Some(ref cooldowns) = self.user_cooldowns {
+            cooldowns.start((msg.channel_id, msg.author.id));
+        }
+
+        if let Some(ref activity) = self.activity {
+            activity.record(msg.channel_id);
+        }
+
+        if let Some(ref webhook) = self.event_webhook {
+            let submitted = thumbnail.filter(|x| !x.random);
+
+            webhook.emit(events::DeclickbaitEvent {
+                video_id: vid_id.to_string(),
+                guild_id: msg.guild_id.map(|x| x.get()),
+                channel_id: msg.channel_id.get(),
+                title: title.title.clone(),
+                title_uuid: title.uuid.clone(),
+                title_votes: title.votes,
+                title_locked: title.locked,
+                trust_tier: self.trust_tiers.tier(title.votes, title.locked).to_string(),
+                thumbnail_uuid: submitted.map(|x| x.uuid.clone()),
+                thumbnail_votes: submitted.map(|x| x.votes),
+                thumbnail_locked: submitted.map(|x| x.locked),
+                timestamp: events::DeclickbaitEvent::now(),
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        log::info!("connected as {}!", ready.user.name);
+        self.health.connected(ctx.shard_id);
+
+        // every shard and every reconnect gets a ready, the commands only need registering once.
+        if self.commands_registered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if let Err(e) = Command::set_global_commands(&ctx.http, commands::all(self)).await {
+            log::error!("failed to register commands: {e:#?}");
+            self.commands_registered.store(false, Ordering::SeqCst);
+        }
+    }
+
+    async fn resume(&self, ctx: Context, _: serenity::all::ResumedEvent) {
+        self.health.connected(ctx.shard_id);
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, event: serenity::all::ShardStageUpdateEvent) {
+        self.health.stage(event.shard_id, event.new);
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Command(command) = interaction {
+            commands::handle(self, &ctx, &command).await;
+        }
+    }
+
+    async fn guild_create(&self, _ctx: Context, guild: serenity::all::Guild, _: Option<bool>) {
+        self.departures.returned(guild.id);
+    }
+
+    async fn guild_delete(
+        &self,
+        _ctx: Context,
+        incomplete: serenity::all::UnavailableGuild,
+        full: Option<serenity::all::Guild>,
+    ) {
+        let name = full.as_ref().map(|x| x.name.as_str()).unwrap_or_default();
+
+        if !self.departures.left(
+            incomplete.id,
+            incomplete.unavailable,
+            std::time::Instant::now(),
+        ) {
+            log::warn!(
+                "guild {} {name} became unavailable, likely a discord outage.",
+                incomplete.id
+            );
+            return;
+        }
+
+        let grace = self.departures.grace();
+        log::info!(
+            "removed from guild {} {name}, forgetting about it in {}s unless it's back by then.",
+            incomplete.id,
+            grace.as_secs()
+        );
+
+        // the cache is all that knows which channels were the guild's.
+        let channels = full
+            .map(|x| {
+                x.channels
+                    .into_keys()
+                    .chain(x.threads.into_iter().map(|x| x.id))
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+
+        // every event is handled in a task of its own, so waiting here holds up nothing else.
+        tokio::time::sleep(grace).await;
+
+        if self
+            .departures
+            .confirm(incomplete.id, std::time::Instant::now())
+        {
+            self.forget_guild(incomplete.id, &channels);
+        }
+    }
+
+    async fn thread_create(&self, ctx: Context, thread: GuildChannel) {
+        // also fired when we get added to an existing thread or gain access to one.
+        let age = Timestamp::now().unix_timestamp() - thread.id.created_at().unix_timestamp();
+
+        if age > FORUM_POST_MAX_AGE {
+            return;
+        }
+
+        let Some(parent_id) = thread.parent_id else {
+            return;
+        };
+
+        let cached = ctx
+            .cache
+            .guild(thread.guild_id)
+            .and_then(|x| x.channels.get(&parent_id).map(|x| x.kind));
+
+        // without the cache, ask discord.
+        let parent_kind = match cached {
+            Some(kind) => Some(kind),
+            None => parent_id
+                .to_channel(&ctx.http)
+                .await
+                .ok()
+                .and_then(|x| x.guild())
+                .map(|x| x.kind),
+        };
+
+        let is_forum_post = parent_kind == Some(ChannelType::Forum);
+
+        if !is_forum_post {
+            return;
+        }
+
+        // the starter message shares its ID with the thread. its message event usually gets
+        // here too, the reply log makes sure only one of them replies.
+        let mut starter = match thread
+            .id
+            .message(&ctx.http, MessageId::new(thread.id.get()))
+            .await
+        {
+            Ok(starter) => starter,
+            Err(e) => {
+                log::warn!("could not fetch starter message of {}: {e:#?}", thread.id);
+                return;
+            }
+        };
+
+        // fetched messages don't carry the guild.
+        starter.guild_id = Some(thread.guild_id);
+
+        self.message(ctx, starter).await;
+    }
+
+    async fn message(&self, ctx: Context, msg: Message) {
+        self.process(ctx, msg, false).await;
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        // discord adding its embeds is an update too, but doesn't make it an edit.
+        if event.edited_timestamp.is_none() {
+            return;
+        }
+
+        let Some(ref content) = event.content else {
+            return;
+        };
+
+        let Some(content) = self.scannable(content) else {
+            return;
+        };
+
+        let added = youtube::find_video_ids(content)
+            .into_iter()
+            .any(|id| !self.seen_links.is_cooling_down(&(event.id, id)));
+
+        if !added {
+            return;
+        }
+
+        let mut msg = match new {
+            Some(msg) => msg,
+            None => match event.channel_id.message(&ctx.http, event.id).await {
+                Ok(msg) => msg,
+                Err(e) => {
+                    log::warn!("could not fetch edited message {}: {e:#?}", event.id);
+                    return;
+                }
+            },
+        };
+
+        if msg.author.id == ctx.cache.current_user().id {
+            return;
+        }
+
+        // fetched messages don't carry the guild.
+        msg.guild_id = event.guild_id;
+
+        log::info!("message {} was edited to link another video.", msg.id);
+
+        self.process(ctx, msg, false).await;
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        if !self.retrigger_reactions
+            || !self.emojis.is(
+                &ctx.cache,
+                reaction.guild_id,
+                emoji::EmojiSlot::Retrigger,
+                &reaction.emoji,
+            )
+        {
+            return;
+        }
+
+        let (Some(guild_id), Some(user_id)) = (reaction.guild_id, reaction.user_id) else {
+            return;
+        };
+
+        let is_mod = reaction.member.as_ref().is_some_and(|member| {
+            permissions::member_in_channel(&ctx.cache, guild_id, reaction.channel_id, member)
+                .is_some_and(|x| x.contains(Permissions::MANAGE_MESSAGES))
+        });
+
+        if !is_mod && !self.owners.contains(&user_id) {
+            return;
+        }
+
+        let mut msg = match reaction.message(&ctx.http).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("could not fetch the message {user_id} asked to retry: {e:#?}");
+                return;
+            }
+        };
+
+        // fetched messages don't carry the guild.
+        msg.guild_id = Some(guild_id);
+
+        log::info!("{user_id} asked to retry message {}.", msg.id);
+
+        self.process(ctx, msg, true).await;
+    }
+}
+
+impl Handler {
+    /// Replies to the videos linked in `msg`. `retrigger` replies again even if we already did
+    /// or the video is cooling down, e.g. when a moderator asks for it.
+    #[tracing::instrument(
+        name = "handle_message",
+        skip_all,
+        fields(guild_id = msg.guild_id.map(|x| x.get()), channel_id = msg.channel_id.get(), video_id)
+    )]
+    async fn process(&self, ctx: Context, mut msg: Message, retrigger: bool) {
+        let is_dm = msg.guild_id.is_none();
+
+        let bot_id = ctx.cache.current_user().id;
+        let mut msg_with_link = Box::new(msg.clone());
+        let mut bypass_checks = false;
+        if let Ok(x) = msg.mentions_me(&ctx.http).await
+            && x
+        {
+            // probably wants to force
+            if let Some(ref x) = msg.referenced_message {
+                // // make it so that if the reply is to a message by us we override all limits and redo it
+                if x.author.id == bot_id {
+                    //     // the reffed msg was ours.
+                    log::warn!("reffed msg was ours, but this path is not implemented yet.")
+                } else if self.opt_outs.contains(x.author.id) {
+                    log::info!("the message replied to is by a user who opted out, leaving it.");
+                    return;
+                } else {
+                    log::info!(
+                        "overriding link to the message replied to by the message that @ed us"
+                    );
+                    msg_with_link = x.clone();
+                    bypass_checks = true;
+                }
+            }
+        }
+
+        let link = safe_content(&ctx.cache, &msg_with_link);
+
+        #[cfg(feature = "metrics")]
+        metrics::MESSAGES.increment();
+
+        let Some(link) = self.scannable(&link) else {
+            return;
+        };
+
+        let links = self.find_videos(link).await;
+        let ids = links.iter().map(|x| x.id.clone()).collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            return;
+        }
+
+        match self.message_gate(
+            &ctx.cache,
+            msg.guild_id,
+            msg.channel_id,
+            msg.author.id,
+            retrigger,
+        ) {
+            Ok(()) => {}
+            Err(Gate::UserCooldown) => {
+                log::debug!(
+                    "{} got a reply in channel {} recently, ignoring their links.",
+                    msg.author.id,
+                    msg.channel_id
+                );
+                return;
+            }
+            Err(Gate::MissingPermissions(missing)) => {
+                log::warn!(
+                    "missing {missing} to reply in channel {}, not trying again there for {} minutes.",
+                    msg.channel_id,
+                    DENIED_CHANNEL_COOLDOWN.as_secs() / 60
+                );
+                self.denied_channels.start(msg.channel_id);
+                return;
+            }
+            Err(_) => return,
+        }
+
+        // only messages with videos in them wait for a turn, the rest are cheap enough.
+        let turn = self.turns.acquire(msg.guild_id).await;
+
+        tracing::Span::current().record("video_id", ids.join(","));
+
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+
+        // held until the replies are sent, so the same videos aren't handled twice meanwhile.
+        let mut claims = Vec::new();
+        let mut fetched = Vec::new();
+
+        for id in ids {
+            // an edit only gets the videos it added looked at.
+            let key = (msg.id, id.clone());
+            if !retrigger && self.seen_links.is_cooling_down(&key) {
+                log::debug!(video_id = id.as_str(); "already looked at {id} in this message, skipping.");
+                continue;
+            }
+            self.seen_links.start(key);
+
+            let Some(claim) = self.replies.claim(msg.id, &id, retrigger) else {
+                log::info!(video_id = id.as_str(); "already replied to {id} for this message, skipping.");
+                continue;
+            };
+
+            match self.video_gate(msg.channel_id, &id, bypass_checks || retrigger) {
+                Ok(()) => {}
+                Err(gate) => {
+                    log::info!(video_id = id.as_str(); "{gate}, skipping {id}.");
+                    continue;
+                }
+            }
+
+            log::info!(video_id = id.as_str(); "de-clickbaiting {id}!");
+
+            // the other videos may well still work out.
+            match self.branding(&id).await {
+                Ok(branding) => {
+                    claims.push(claim);
+                    fetched.push((id, branding));
+                }
+                // nobody submitted anything for it yet, which is the usual case.
+                Err(DeArrowError::NotFound) => {
+                    log::info!(video_id = id.as_str(); "DeArrow has no branding for {id}, skipping.")
+                }
+                Err(e) => {
+                    log::error!(video_id = id.as_str(); "failed to get branding for {id}! {e:#?}")
+                }
+            }
+        }
+
+        // in DMs we can always embed.
+        let can_embed = msg.guild_id.is_none_or(|guild_id| {
+            permissions::in_channel(&ctx.cache, guild_id, msg.channel_id)
+                .is_none_or(|x| x.contains(Permissions::EMBED_LINKS))
+        });
+
+        // without --plain-text-fallback, missing it already turned us away above.
+        if !can_embed && !self.embed_warnings.is_cooling_down(&msg.channel_id) {
+            self.embed_warnings.start(msg.channel_id);
+            log::warn!(
+                "missing the Embed Links permission in channel {}. grant it to the bot, falling back to plain text replies.",
+                msg.channel_id
+            );
+        }
+
+        let override_thumb = msg.content.contains("DeArrow:force_thumbnail")
+            || msg_with_link.content.contains("DeArrow:force_thumbnail");
+
+        // the nickname is only known for messages that triggered the event directly.
+        let sharer = self.show_sharer.then(|| {
+            msg_with_link
+                .member
+                .as_ref()
+                .and_then(|x| x.nick.as_deref())
+                .unwrap_or(msg_with_link.author.display_name())
+        });
+
+        // discord's embeds of the original message, fetched once the first video needs them.
+        let mut original_embeds = None;
+
+        let mut videos = Vec::new();
+        let mut lines = Vec::new();
+
+        for (id, branding) in &fetched {
+            // no use waiting on discord's embed for a video the title gates turn away anyway.
+            let embed_titles = if self.policy.skip_matching_embeds
+                && !bypass_checks
+                && self.policy.choose_title(branding, false).is_ok()
+            {
+                let embeds = match original_embeds {
+                    Some(ref embeds) => embeds,
+                    None if msg_with_link.embeds.is_empty() => {
+                        log::info!("waiting for discord to embed the video to compare titles!");
+                        original_embeds.insert(
+                            wait_for_embeds(&ctx, &msg_with_link, self.embed_wait)
+                                .await
+                                .unwrap_or_default(),
+                        )
+                    }
+                    None => original_embeds.insert(msg_with_link.embeds.clone()),
+                };
+
+                embeds.iter().filter_map(|x| x.title.clone()).collect()
+            } else {
+                Vec::new()
+            };
+
+            let decision = self.policy.should_reply(decision::ReplyDecisionInputs {
+                branding,
+                forced: bypass_checks,
+                forced_thumbnail: override_thumb,
+                embed_titles: &embed_titles,
+            });
+
+            let (title, chosen) = match decision {
+                decision::Decision::Reply { title, thumbnail } => (title, thumbnail),
+                decision::Decision::Skip(decision::Skip::ReviewedFine)
+                    if self.policy.reviewed_title == ReviewedTitle::React =>
+                {
+                    log::info!(video_id = id.as_str(); "title of {id} was reviewed and is fine, reacting.");
+
+                    if let Err(e) = msg.react(&ctx.http, '✅').await {
+                        log::warn!("could not react to message: {e:#?}");
+                    }
+
+                    continue;
+                }
+                decision::Decision::Skip(decision::Skip::NoTitle) => {
+                    // a crowd-sourced thumbnail is still worth showing, a random frame isn't.
+                    let thumbnail = self
+                        .policy
+                        .choose_thumbnail(branding, override_thumb)
+                        .ok()
+                        .filter(|x| !x.random);
+
+                    let mut part = None;
+
+                    if let Some(thumbnail) = thumbnail {
+                        log::info!(video_id = id.as_str(); "no titles were submitted for {id}, showing just the thumbnail.");
+                        part = self.thumbnail_only_part(&ctx, &msg, id, thumbnail).await;
+                    }
+
+                    if part.is_none() && self.show_original_when_empty {
+                        log::info!(video_id = id.as_str(); "no titles were submitted for {id}, showing the original title.");
+                        part = self.original_part(&ctx, &msg, id).await;
+                    }
+
+                    match part {
+                        Some(part) => videos.push(Video {
+                            id,
+                            part,
+                            reply: None,
+                        }),
+                        None => {
+                            log::warn!(video_id = id.as_str(); "{}. skipping {id}.", decision::Skip::NoTitle)
+                        }
+                    }
+
+                    continue;
+                }
+                decision::Decision::Skip(skip @ decision::Skip::RecapitalizationOnly) => {
+                    log::info!(video_id = id.as_str(); "{skip}. skipping {id}.");
+                    continue;
+                }
+                decision::Decision::Skip(decision::Skip::MatchesEmbed) => {
+                    log::info!(
+                        "original embed already shows the de-arrowed title of {id}. skipping."
+                    );
+                    continue;
+                }
+                decision::Decision::Skip(skip) => {
+                    log::warn!(video_id = id.as_str(); "{skip}. skipping {id}.");
+                    continue;
+                }
+            };
+
+            if !self.video_exists(id).await {
+                log::info!(video_id = id.as_str(); "{id} is no longer available on youtube, skipping.");
+                continue;
+            }
+
+            if !can_embed && self.plain_text_fallback {
+                let mut line = format!(
+                    "{} **{}** ({})",
+                    self.emojis
+                        .resolve(&ctx.cache, msg.guild_id, emoji::EmojiSlot::Compact),
+                    title.title,
+                    self.trust_tiers.tier(title.votes, title.locked)
+                );
+
+                if let Some(ref footer) = self.embed_footer {
+                    line.push_str(&format!(" · {footer}"));
+                }
+
+                lines.push((id, title, line));
+                continue;
+            }
+
+            let chosen = chosen
+                .map_err(|e| {
+                    if !matches!(e, decision::NoThumbnail::Disabled) {
+                        log::warn!("{e}.");
+                    }
+                })
+                .ok();
+
+            // a thumbnail that exists but failed to download, to be added to the reply later.
+            let mut pending_thumbnail = None;
+            // youtube's own, for when DeArrow's failed to download and won't be added later.
+            let mut fallback = None;
+            // DeArrow's, but too large for discord even recompressed.
+            let mut too_large = false;
+
+            let thumb = match chosen {
+                Some(thumbnail) => {
+                    match self.fetch_thumbnail_retrying(id, thumbnail.timestamp).await {
+                        Ok(thumb) => Some((thumb, thumbnail)),
+                        Err(e) => {
+                            match e {
+                                DeArrowError::NotFound => log::info!(
+                                    video_id = id.as_str(); "DeArrow has no thumbnail for {id} at the chosen timestamp yet."
+                                ),
+                                DeArrowError::TooLarge => {
+                                    log::warn!(video_id = id.as_str(); "the thumbnail for {id} is too large to attach, even recompressed.");
+                                    too_large = true;
+                                }
+                                e => log::error!("failed to retrieve thumbnail: {e:#?}"),
+                            }
+
+                            // it'd come out just as large later.
+                            if self.background_thumbnails && !too_large {
+                                pending_thumbnail = Some(thumbnail);
+                            } else if self.youtube_thumbnail_fallback {
+                                match youtube::thumbnail(&self.client, id).await {
+                                    Ok(thumb) => fallback = Some(thumb),
+                                    Err(e) => log::warn!(
+                                        "failed to get youtube's thumbnail for {id} either: {e:#?}"
+                                    ),
+                                }
+                            }
+
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            let (thumb, thumbnail) = thumb.unzip();
+            let fell_back = fallback.is_some();
+            let thumb = thumb.or(fallback);
+
+            let thumbnail_status = match thumbnail {
+                Some(thumbnail) => describe_thumbnail(thumbnail),
+                None if pending_thumbnail.is_some() => "still loading".to_string(),
+                None if fell_back => "YouTube's own as a fallback, not crowd-sourced".to_string(),
+                None if too_large => "too large to attach".to_string(),
+                None => match self.policy.thumbnail_mode {
+                    ThumbnailMode::Disabled => "disabled by dev",
+                    ThumbnailMode::Enabled if self.policy.locked_only => {
+                        "disabled by dev (lock-only)"
+                    }
+                    ThumbnailMode::Enabled => "not found",
+                    ThumbnailMode::OnlyLocked => "disabled by dev (lock-only)",
+                }
+                .to_string(),
+            };
+
+            // one more request, and the reply is fine without it.
+            let oembed = if self.show_channel || self.show_original_title {
+                youtube::oembed(&self.client, id)
+                    .await
+                    .map_err(|e| log::warn!(video_id = id.as_str(); "failed to look up {id} on youtube: {e:#?}"))
+                    .ok()
+            } else {
+                None
+            };
+
+            let author = oembed
+                .as_ref()
+                .filter(|_| self.show_channel)
+                .map(|x| CreateEmbedAuthor::new(&x.author_name).url(&x.author_url));
+
+            // nothing to compare if only the capitalization changed.
+            let original = oembed
+                .as_ref()
+                .filter(|x| self.show_original_title && !x.title.eq_ignore_ascii_case(&title.title))
+                .map(|x| x.title.clone());
+
+            // only worth a link of its own if the poster meant a particular moment.
+            let watch = links
+                .iter()
+                .find(|x| x.id == *id)
+                .and_then(|x| Some((x.start?, x.watch_url())));
+
+            let decorate = |mut embed: CreateEmbed| {
+                if let Some(ref author) = author {
+                    embed = embed.author(author.clone());
+                }
+
+                if let Some((start, ref url)) = watch {
+                    embed = embed.field(
+                        "Watch original",
+                        format!("[from {}]({url})", youtube::format_timestamp(start)),
+                        false,
+                    );
+                }
+
+                match original {
+                    Some(ref original) => embed.field("Original", original, false),
+                    None => embed,
+                }
+            };
+
+            let embed = decorate(self.build_embed(id, branding, title, &thumbnail_status, sharer));
+
+            let late = pending_thumbnail.map(|thumbnail| {
+                let embed =
+                    self.build_embed(id, branding, title, &describe_thumbnail(thumbnail), sharer);
+                (thumbnail, decorate(embed))
+            });
+
+            videos.push(Video {
+                id,
+                part: reply::ReplyPart {
+                    embed,
+                    thumbnail: thumb,
+                    alt_text: self.thumbnail_alt_text(&title.title),
+                    buttons: self.buttons(id),
+                },
+                reply: Some(Declickbaited {
+                    title,
+                    thumbnail,
+                    late,
+                }),
+            });
+
+            log::info!(video_id = id.as_str(); "Successfully generated de-clickbaited embed for {id}!");
+        }
+
+        let reference = self.reference(&msg);
+
+        if !lines.is_empty() {
+            let content = lines
+                .iter()
+                .map(|(_, _, line)| line.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            let sent = reply::send_text(&ctx.http, msg.channel_id, reference, content).await;
+            self.note_denied(msg.channel_id, &sent);
+
+            if sent.any() {
+                for (id, title, _) in &lines {
+                    self.record_reply(&msg, id, title, None);
+                }
+            }
+        }
+
+        if videos.is_empty() {
+            return;
+        }
+
+        let thumb_present = videos.iter().any(|x| x.part.thumbnail.is_some());
+
+        let parts = videos.iter().map(|x| x.part.clone()).collect();
+        let sent = reply::send(
+            &ctx.http,
+            msg.channel_id,
+            reference,
+            parts,
+            self.embed_limit,
+        )
+        .await;
+        self.note_denied(msg.channel_id, &sent);
+
+        let mut late = Vec::new();
+
+        for (index, video) in videos.iter().enumerate() {
+            if !sent.delivered(index) {
+                continue;
+            }
+
+            let Some(ref declickbaited) = video.reply else {
+                self.replies.insert(msg.id, video.id);
+                continue;
+            };
+
+            let title = declickbaited.title;
+
+            if let Some((thumbnail, ref embed)) = declickbaited.late {
+                // editing in one thumbnail would clobber the other videos' embeds.
+                match sent.alone_in(index) {
+                    Some(reply) => late.push(self.add_thumbnail_later(
+                        &ctx,
+                        reply.clone(),
+                        video.id,
+                        thumbnail,
+                        embed.clone(),
+                        self.thumbnail_alt_text(&title.title),
+                    )),
+                    None => log::info!(
+                        "not adding the late thumbnail for {}, its reply shows other videos too.",
+                        video.id
+                    ),
+                }
+            }
+
+            self.record_reply(&msg, video.id, title, declickbaited.thumbnail);
+
+            let borderline =
+                !title.locked && title.votes < self.policy.min_title_votes + self.trust_poll_margin;
+
+            if self.trust_polls && borderline {
+                let can_poll = msg.guild_id.is_none_or(|guild_id| {
+                    permissions::in_channel(&ctx.cache, guild_id, msg.channel_id)
+                        .is_none_or(|x| x.contains(Permissions::SEND_POLLS))
+                });
+
+                if can_poll {
+                    reply::send_trust_poll(&ctx.http, msg.channel_id, reference, &title.title)
+                        .await;
+                } else {
+                    log::info!("no permission to send polls in {}.", msg.channel_id);
+                }
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if sent.any() {
+            self.timings.record(started.elapsed());
+        }
+
+        // waiting on Discord to embed the link takes no work, let the next message have its turn.
+        drop(turn);
+
+        let remove_embed = async {
+            // we can't suppress embeds on someone else's message in DMs.
+            if self.policy.thumbnail_mode != ThumbnailMode::Disabled
+                && thumb_present
+                && (self.remove_embed || self.suppress_embed_channels.contains(&msg.channel_id))
+                && !is_dm
+            {
+                let embedded =
+                    wait_until_embedded(&ctx, &msg, self.embed_wait, self.embed_settle).await;
+
+                // suppressing now could hide an embed that shows up later, with nothing to say
+                // ours replaces it.
+                if !embedded {
+                    log::warn!(
+                        "discord didn't embed the video within {}ms, leaving the original message alone.",
+                        self.embed_wait.as_millis()
+                    );
+                    return;
+                }
+
+                log::info!("editing message to remove original embed!");
+
+                if let Err(e) = self
+                    .edits
+                    .edit(
+                        &ctx.http,
+                        &mut msg,
+                        EditMessage::new().suppress_embeds(true),
+                    )
+                    .await
+                {
+                    log::error!("unable to edit sent message: {e:#?}");
+                }
+            }
+        };
+
+        // the late thumbnails wait a while before they're tried again, removing the embed
+        // shouldn't wait on them.
+        futures::join!(remove_embed, futures::future::join_all(late));
+    }
+
+    /// The part of `content` to look for links in, if any.
+    fn scannable<'a>(&self, content: &'a str) -> Option<&'a str> {
+        self.oversized_content.scannable(content, self.max_scan_len)
+    }
+
+    /// The videos linked in `content`, or the video a clip linked in there is cut from.
+    async fn find_videos(&self, content: &str) -> Vec<youtube::VideoLink> {
+        let links = youtube::find_links(content);
+
+        if !links.is_empty() {
+            return links;
+        }
+
+        let (Some(clips), Some(clip_id)) = (&self.clips, youtube::find_clip(content)) else {
+            return links;
+        };
+
+        match clips.resolve(clip_id).await {
+            Ok(id) => {
+                log::info!("resolved clip {clip_id} to {id}.");
+                vec![youtube::VideoLink { id, start: None }]
+            }
+            Err(e) => {
+                log::warn!("failed to resolve clip {clip_id}: {e:#?}");
+                links
+            }
+        }
+    }
+}
+
+/// One video's embed in a reply, and what to do once it went out.
+struct Video<'a> {
+    id: &'a str,
+    part: reply::ReplyPart,
+    /// `None` for videos shown with their original title.
+    reply: Option<Declickbaited<'a>>,
+}
+
+struct Declickbaited<'a> {
+    title: &'a BrandingTitle,
+    thumbnail: Option<&'a BrandingThumbnail>,
+    /// A thumbnail that failed to download and the embed to edit in once it does.
+    late: Option<(&'a BrandingThumbnail, CreateEmbed)>,
+}
+
+/// The log level until an owner changes it with /loglevel.
+const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+/// How many votes an unlocked title needs before we show it, unless `--min-title-votes` says
+/// otherwise.
+const MIN_TITLE_VOTES: isize = 2;
+
+/// Discord cuts attachment descriptions off at 1024 characters.
+const MAX_ALT_TEXT_LEN: usize = 1024;
+
+/// How many seconds old a thread can be for its starter message to still count as new.
+const FORUM_POST_MAX_AGE: i64 = 60;
+
+/// Upper bound on how many videos the per-video bookkeeping remembers at once.
+const MAX_TRACKED_VIDEOS: usize = 10_000;
+
+/// How long we hold off on replying in a channel after Discord refused us there.
+const DENIED_CHANNEL_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Upper bound on how many channels the per-channel bookkeeping remembers at once.
+const MAX_TRACKED_CHANNELS: usize = 10_000;
+
+/// Upper bound on how many guilds the per-guild bookkeeping remembers at once.
+const MAX_TRACKED_GUILDS: usize = 10_000;
+
+/// Upper bound on how many users the per-user bookkeeping remembers at once.
+const MAX_TRACKED_USERS: usize = 10_000;
+
+#[derive(Parser)]
+struct Args {
+    #[arg(long, env)]
+    /// A TOML file with any of the other settings, keyed by their long names, as in `thumbnail_mode = "enabled"`. The command line and env vars take precedence over it.
+    config: Option<PathBuf>,
+
+    #[arg(long, env)]
+    /// The discord token for the bot
+    token: String,
+
+    #[arg(long, env, default_value_t = logging::LogFormat::Text)]
+    /// How log lines are written. "text" for people, "json" for one object per line with timestamp, level, target, message and fields like video_id.
+    log_format: logging::LogFormat,
+
+    #[arg(long, env, default_value_t = ThumbnailMode::OnlyLocked)]
+    /// The Thumbnail Mode. Indicates whether or not thumbnails should be embedded. "Locked" here refers to a crowd-sourced thumbnail having reached consensus status.
+    thumbnail_mode: ThumbnailMode,
+
+    #[arg(long, env)]
+    /// Whether to remove the original embed from the sender.
+    remove_embed: bool,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated channel IDs in which the original embed is always removed, even if --remove-embed is off. With --remove-embed on, this has no effect.
+    suppress_embed_channels: Vec<NonZeroU64>,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated channel IDs to de-clickbait in, including their threads. Everywhere if unset.
+    allowed_channels: Vec<NonZeroU64>,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated guild IDs to de-clickbait in. Every guild if unset.
+    allowed_guilds: Vec<NonZeroU64>,
+
+    #[arg(long, env)]
+    /// Whether to de-clickbait links sent to the bot in direct messages.
+    allow_dm: bool,
+
+    #[arg(long, env)]
+    /// Whether to wait for Discord's embed and skip replying if it already shows the de-arrowed title, e.g. for users of the DeArrow browser extension.
+    skip_matching_embeds: bool,
+
+    #[arg(long, env)]
+    /// Only de-clickbait videos that are at least this many seconds long.
+    min_video_duration: Option<f32>,
+
+    #[arg(long, env, default_value_t = UnknownDuration::Process)]
+    /// Whether videos with an unknown duration are processed or skipped when --min-video-duration is set.
+    unknown_duration: UnknownDuration,
+
+    #[arg(long, env, default_value_t = TitleSelection::First)]
+    /// Which title counts as the best one: First (DeArrow's own ranking), MostVotes or LockedFirst.
+    title_selection: TitleSelection,
+
+    #[arg(long, env, default_value_t = TitleMatch::Exact)]
+    /// How --skip-matching-embeds compares titles. "loose" ignores case, emoji and punctuation, so only substantive changes get a reply.
+    title_match: TitleMatch,
+
+    #[arg(long, env)]
+    /// Whether to link to the video's page in a DeArrow submission browser, where all submissions can be seen and voted on.
+    link_dearrow_page: bool,
+
+    #[arg(long, env, default_value = "https://sb.ltn.fi/video/{video_id}/")]
+    /// The page linked to by --link-dearrow-page. `{video_id}` is replaced with the video's ID.
+    dearrow_page_url: String,
+
+    #[arg(long, env, default_value = "{title} (DeArrow thumbnail)")]
+    /// The description attached thumbnails get for screen readers. `{title}` is replaced with the de-arrowed title. Empty to leave them undescribed.
+    thumbnail_alt_text: String,
+
+    #[arg(long, env, default_value_t = 8 * 1024 * 1024)]
+    /// Thumbnails larger than this many bytes are re-encoded as smaller JPEGs to fit Discord's attachment limit, or left out if they still don't.
+    max_thumbnail_bytes: usize,
+
+    #[arg(long, env, default_value = "De-Clickbait provided by DeArrow API.")]
+    /// The footer of the replies, next to the sharer and machine tag if those are shown. Empty to leave it out.
+    embed_footer: String,
+
+    #[arg(long, env)]
+    /// The accent color of the reply embeds, in hex like #00aaff. Discord's default if unset.
+    embed_color: Option<EmbedColor>,
+
+    #[arg(long, env)]
+    /// Whether to credit the display name of whoever shared the link in the embed footer.
+    show_sharer: bool,
+
+    #[arg(long, env)]
+    /// Whether to post a poll asking the channel if the title is any good, for unlocked titles with only barely enough votes.
+    trust_polls: bool,
+
+    #[arg(long, env, default_value_t = 2)]
+    /// How many votes past the minimum an unlocked title still counts as barely trusted for --trust-polls.
+    trust_poll_margin: isize,
+
+    #[arg(long, env)]
+    /// Whether to de-clickbait YouTube clips, which requires fetching the clip's page from YouTube to find the video it is from.
+    resolve_clips: bool,
+
+    #[arg(long, env)]
+    /// A URL to POST a JSON event to for every de-clickbait, for external integrations.
+    event_webhook_url: Option<String>,
+
+    #[arg(long, env, default_value_t = 4)]
+    /// How many thumbnails may be downloaded at once.
+    max_thumbnail_concurrency: usize,
+
+    #[arg(long, env)]
+    /// If a thumbnail fails to download, retry once after this many milliseconds.
+    thumbnail_retry_delay_ms: Option<u64>,
+
+    #[arg(long, env)]
+    /// If a thumbnail still fails to download, reply without it and edit it in once a background retry succeeds.
+    background_thumbnails: bool,
+
+    #[arg(long, env)]
+    /// Show YouTube's own thumbnail, labelled as a fallback, when DeArrow's can't be downloaded and --background-thumbnails isn't going to add it later.
+    youtube_thumbnail_fallback: bool,
+
+    #[arg(long, env)]
+    /// Whether to reply in plain text in channels where the bot lacks the Embed Links permission.
+    plain_text_fallback: bool,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated <guild id>:<command> pairs of slash commands to turn off in a guild. /dearrow, /dearrow-at and /dearrow-explain can be turned off.
+    disabled_commands: Vec<commands::GuildCommand>,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated <guild id>:<slot>:<emoji id> overrides, to use a guild's custom emoji instead of the unicode defaults. The slots are `compact`, which prefixes plain text replies, and `retrigger`, the reaction with which moderators have a message processed again.
+    guild_emoji: Vec<emoji::GuildEmoji>,
+
+    #[arg(long, env, default_value_t = 5)]
+    /// Minimum votes for an unlocked title to be badged as "Trusted".
+    trusted_votes: isize,
+
+    #[arg(long, env, default_value_t = 1)]
+    /// Minimum votes for an unlocked title to be badged as "Community". Anything below is "Unverified".
+    community_votes: isize,
+
+    #[arg(long, env)]
+    /// Don't reply to a video again within this many seconds of the last reply to it, in any channel.
+    video_cooldown: Option<u64>,
+
+    #[arg(long, env)]
+    /// Don't reply to a video again within this many seconds of the last reply to it in the same channel, no matter who reposts it.
+    channel_video_cooldown: Option<u64>,
+
+    #[arg(long, env)]
+    /// Ignore a user's links within this many seconds of the last reply to them in the same channel.
+    cooldown_secs: Option<u64>,
+
+    #[arg(long, env, default_value_t = 600)]
+    /// How many seconds DeArrow's branding and thumbnails for a video are reused for reposts of it. 0 disables the cache.
+    response_cache_ttl: u64,
+
+    #[arg(long, env, default_value_t = 1000)]
+    /// How many videos' branding, and how many thumbnails, the response cache holds at most.
+    response_cache_size: usize,
+
+    #[arg(long, env)]
+    /// Check with YouTube that a video still exists before replying to it, and stay silent on removed or private ones. Costs an extra request per video.
+    verify_video_exists: bool,
+
+    #[arg(long, env)]
+    /// Show the video's original YouTube title in the reply, to see what was changed. Costs an extra request per video, shared with --show-channel.
+    show_original_title: bool,
+
+    #[arg(long, env)]
+    /// Timestamp the reply with when its branding was fetched from DeArrow, which shows when it comes from the response cache.
+    show_fetch_time: bool,
+
+    #[arg(long, env)]
+    /// Directory to keep downloaded thumbnails in across restarts. Not kept on disk if unset.
+    thumbnail_cache_dir: Option<PathBuf>,
+
+    #[arg(long, env)]
+    /// Upper bound, in megabytes, on the thumbnail cache directory. The least recently used thumbnails are evicted beyond it.
+    thumbnail_cache_max_mb: Option<u64>,
+
+    #[arg(long, env)]
+    /// Evict cached thumbnails that weren't used for this many seconds.
+    thumbnail_cache_max_age: Option<u64>,
+
+    #[arg(long, env, default_value_t = 3600)]
+    /// How often, in seconds, the thumbnail cache directory is cleaned up. It's also cleaned up on startup.
+    thumbnail_cache_cleanup_interval: u64,
+
+    #[arg(long, env)]
+    /// Where to persist reply records and the video, channel video and user cooldowns, so a restart doesn't cause re-replies to re-delivered or reposted links. In-memory only if unset.
+    storage_path: Option<PathBuf>,
+
+    #[arg(long, env)]
+    /// JSON file to keep the users who opted out with /dearrow-optout in across restarts. In-memory only if unset.
+    optout_path: Option<PathBuf>,
+
+    #[arg(long, env, default_value_t = 3600)]
+    /// How long, in seconds, reply records are kept around.
+    reply_log_max_age: u64,
+
+    #[arg(long, env, default_value_t = 20)]
+    /// How many slash commands may be used per guild within --command-limit-window.
+    guild_command_limit: usize,
+
+    #[arg(long, env, default_value_t = 5)]
+    /// How many slash commands each user may use within --command-limit-window.
+    user_command_limit: usize,
+
+    #[arg(long, env, default_value_t = 60)]
+    /// The window, in seconds, the slash command limits apply to.
+    command_limit_window: u64,
+
+    #[arg(long, env, default_value_t = 300)]
+    /// How often, in seconds, expired entries are evicted from the in-memory bookkeeping.
+    sweep_interval: u64,
+
+    #[arg(long, env, default_value = "https://sponsor.ajay.app")]
+    /// Where to get branding from, e.g. a self-hosted DeArrow mirror. `/api/branding` is appended.
+    branding_api_base: String,
+
+    #[arg(long, env, default_value = "https://dearrow-thumb.ajay.app")]
+    /// Where to get thumbnails from, e.g. a self-hosted thumbnail generator. `/api/v1/getThumbnail` is appended.
+    thumbnail_api_base: String,
+
+    #[arg(long, env, default_value_t = 3)]
+    /// How many times a request to DeArrow is tried in total when it fails with a network error or a 5xx. 4xx responses aren't retried.
+    retry_attempts: u32,
+
+    #[arg(long, env, default_value_t = 10_000)]
+    /// Timeout, in milliseconds, for requests to DeArrow and YouTube.
+    request_timeout_ms: u64,
+
+    #[arg(long, env, default_value_t = 32)]
+    /// How many messages are processed at once. Beyond that, guilds take turns, so one flooding the bot doesn't hold up the others.
+    max_concurrent_messages: usize,
+
+    #[arg(long, env, default_value_t = 4)]
+    /// How many messages from the same guild are processed at once.
+    max_concurrent_messages_per_guild: usize,
+
+    #[arg(long, env, default_value_t = 1000)]
+    /// Minimum time, in milliseconds, between two edits the bot makes to the same message. Later edits wait their turn.
+    min_edit_interval_ms: u64,
+
+    #[arg(long, env)]
+    /// When DeArrow has no titles for a video, reply with its original YouTube title instead of staying silent, to flag gaps in DeArrow's coverage.
+    show_original_when_empty: bool,
+
+    #[arg(long, env)]
+    /// Append a machine-readable `dearrow:<video id>:<title uuid>` tag to the reply's footer, for bridges and archival tooling.
+    embed_machine_tag: bool,
+
+    #[arg(long, env)]
+    /// Run without serenity's cache, for low-memory deployments. Permission checks are skipped and guild emoji overrides fall back to the unicode defaults.
+    no_cache: bool,
+
+    #[arg(long, env)]
+    /// Only show titles and thumbnails the community has locked in, skipping the reply entirely if the title isn't locked.
+    locked_only: bool,
+
+    #[arg(long, env, default_value_t = MIN_TITLE_VOTES, allow_negative_numbers = true)]
+    /// How many votes an unlocked title needs before it's shown. Locked titles are always shown.
+    min_title_votes: isize,
+
+    #[arg(long, env, default_value_t = 0, allow_negative_numbers = true)]
+    /// How many votes an unlocked thumbnail needs before it's shown, 0 only turning away downvoted ones. Locked thumbnails are always shown.
+    min_thumbnail_votes: isize,
+
+    #[arg(long, env)]
+    /// Prefer a trusted thumbnail with a user-submitted frame over a better-voted one that leaves the frame up to the server.
+    prefer_timestamped_thumbnails: bool,
+
+    #[arg(long, env)]
+    /// When no submitted thumbnail is good enough, show the frame at the random time DeArrow picks for the video instead of none. Costs a thumbnail download per such video. Not with --locked-only or --thumbnail-mode onlylocked.
+    random_frame_fallback: bool,
+
+    #[arg(long, env)]
+    /// Prefer a trusted community rewrite over a better-voted recapitalization of the original title, unless only the recapitalization is locked.
+    prefer_custom_titles: bool,
+
+    #[arg(long, env, default_value_t = OutputStyle::Embed)]
+    /// How replies are laid out. "components" adds link buttons to YouTube and DeArrow below the embed. Discord's container layouts aren't available in this build, so the classic embed is used for the rest.
+    output_style: OutputStyle,
+
+    #[arg(long, env, default_value_t = ReviewedTitle::Silent)]
+    /// What to do when the community locked in the original title as fine: "silent", "react" with ✅, or "reply" as usual.
+    reviewed_title: ReviewedTitle,
+
+    #[arg(long, env)]
+    /// Don't reply when the chosen title is the original one, so DeArrow only fixed its capitalization.
+    skip_recapitalization_only: bool,
+
+    #[arg(long, env, default_value_t = reply::ReplyMode::Reference)]
+    /// Whether the bot replies to the message with the link ("reference") or posts a message of its own ("standalone").
+    reply_mode: reply::ReplyMode,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated <channel id>:<reply mode> overrides of --reply-mode, e.g. for announcement channels.
+    channel_reply_mode: Vec<reply::ChannelReplyMode>,
+
+    #[arg(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    /// Whether the videos a message links go into one reply together, rather than a reply each. "false" is the same as --max-embeds-per-message 1 with --embed-overflow split.
+    batch_replies: bool,
+
+    #[arg(long, env, default_value_t = reply::MAX_EMBEDS)]
+    /// How many embeds go into one reply when a message links several videos, at most 10. 1 gives each video a reply of its own.
+    max_embeds_per_message: usize,
+
+    #[arg(long, env, default_value_t = reply::Overflow::Split)]
+    /// What happens to embeds beyond --max-embeds-per-message. "split" sends them in further messages, "drop" leaves them out with a "+N more" note.
+    embed_overflow: reply::Overflow,
+
+    #[arg(long, env, default_value_t = 16 * 1024)]
+    /// How many bytes of a message are looked through for links, to bound the work a wall of text can cause.
+    max_content_scan_len: usize,
+
+    #[arg(long, env, default_value_t = OversizedContent::Prefix)]
+    /// What happens to messages longer than --max-content-scan-len. "prefix" looks through the start of them, "skip" ignores them.
+    oversized_content: OversizedContent,
+
+    #[arg(long, env, value_delimiter = ',')]
+    /// Comma-separated user IDs allowed to use owner commands like /maintenance.
+    owner_ids: Vec<NonZeroU64>,
+
+    #[arg(long, env)]
+    /// Show the uploading channel as the embed's author, looked up through YouTube's oEmbed endpoint.
+    show_channel: bool,
+
+    #[arg(long, env, default_value_t = 5000)]
+    /// How many milliseconds to wait for Discord to embed a link, before removing its embed or comparing titles with it. Slow Discord embeds may need more.
+    embed_wait_ms: u64,
+
+    #[arg(long, env, default_value_t = 0)]
+    /// Before removing the original embed, wait up to this many milliseconds for it to settle if Discord already embedded the link.
+    embed_settle_ms: u64,
+
+    #[arg(long, env)]
+    /// Whether to process a message's link again when someone with Manage Messages (or an owner) reacts with 🔁, or the guild's `retrigger` emoji from --guild-emoji, even if it was replied to or skipped before.
+    retrigger_reactions: bool,
+
+    #[arg(long, env)]
+    /// A channel ID to periodically post a summary of the bot's activity to.
+    summary_channel: Option<NonZeroU64>,
+
+    #[arg(long, env, default_value_t = 7 * 24 * 60 * 60)]
+    /// How often, in seconds, to post the activity summary to --summary-channel.
+    summary_interval: u64,
+
+    #[arg(long, env, default_value_t = 300)]
+    /// How many seconds a guild has to come back after the bot was removed from it, before its cooldowns and limits are forgotten. Guilds that only became unavailable in an outage are never forgotten.
+    guild_removal_grace: u64,
+
+    #[arg(long, env, default_value_t = 10)]
+    /// How many seconds to let messages that are being processed finish when shutting down.
+    shutdown_grace_secs: u64,
+
+    #[cfg(feature = "metrics")]
+    #[arg(long, env, default_value_t = 300)]
+    /// How often, in seconds, to log percentiles of the end-to-end handler timings.
+    metrics_log_interval: u64,
+
+    #[cfg(feature = "metrics")]
+    #[arg(long, env)]
+    /// Serve the metrics for Prometheus on this port, at /metrics. Not served if unset.
+    metrics_port: Option<u16>,
+
+    #[arg(long, env)]
+    /// Answer health checks on this port, at /healthz: 200 once connected to the gateway, 503 before and while reconnecting. Not served if unset.
+    health_port: Option<u16>,
+
+    #[cfg(feature = "otlp")]
+    #[arg(long, env)]
+    /// An OTLP/HTTP endpoint to export traces of the message handling to, e.g. http://localhost:4318/v1/traces.
+    otlp_endpoint: Option<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    // read at runtime, so debug builds work without the file too.
+    #[cfg(debug_assertions)]
+    if let Ok(token) = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/src/token")) {
+        // wtf lmao why is this unsafe
+        unsafe { std::env::set_var("TOKEN", token.trim()) };
+    }
+
+    let args = config::parse_args().expect("failed to load config");
+
+    logging::init(args.log_format);
+    log::set_max_level(DEFAULT_LOG_LEVEL);
+
+    #[cfg(feature = "otlp")]
+    let tracer_provider = args
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| otlp::init(endpoint).expect("failed to set up trace export"));
+
+    let max_age = std::time::Duration::from_secs(args.reply_log_max_age);
+
+    let store = args
+        .storage_path
+        .as_ref()
+        .map(|path| state::Store::open(path, max_age).expect("failed to open storage"));
+
+    let mut sweeper = state::Sweeper::default();
+
+    let replies = Arc::new(state::ReplyLog::new(
+        max_age,
+        store.as_ref().map(|store| {
+            store
+                .timestamps("replies")
+                .expect("failed to open reply records")
+        }),
+    ));
+    sweeper.register("reply records", replies.clone());
+
+    let embed_warnings = Arc::new(state::Cooldowns::new(
+        std::time::Duration::from_secs(60 * 60),
+        MAX_TRACKED_CHANNELS,
+    ));
+    sweeper.register("embed permission warnings", embed_warnings.clone());
+
+    let denied_channels = Arc::new(state::Cooldowns::new(
+        DENIED_CHANNEL_COOLDOWN,
+        MAX_TRACKED_CHANNELS,
+    ));
+    sweeper.register("denied channels", denied_channels.clone());
+
+    let command_limit_window = std::time::Duration::from_secs(args.command_limit_window);
+
+    let guild_command_limits = Arc::new(state::RateLimiter::new(
+        args.guild_command_limit,
+        command_limit_window,
+        MAX_TRACKED_GUILDS,
+    ));
+    sweeper.register("guild command limits", guild_command_limits.clone());
+
+    let user_command_limits = Arc::new(state::RateLimiter::new(
+        args.user_command_limit,
+        command_limit_window,
+        MAX_TRACKED_USERS,
+    ));
+    sweeper.register("user command limits", user_command_limits.clone());
+
+    let video_cooldowns = args.video_cooldown.map(|secs| {
+        let cooldowns = Arc::new(persisted_cooldowns(
+            store.as_ref(),
+            "video_cooldowns",
+            std::time::Duration::from_secs(secs),
+            MAX_TRACKED_VIDEOS,
+        ));
+        sweeper.register("video cooldowns", cooldowns.clone());
+        cooldowns
+    });
+
+    let channel_video_cooldowns = args.channel_video_cooldown.map(|secs| {
+        let cooldowns = Arc::new(persisted_cooldowns(
+            store.as_ref(),
+            "channel_video_cooldowns",
+            std::time::Duration::from_secs(secs),
+            MAX_TRACKED_VIDEOS,
+        ));
+        sweeper.register("channel video cooldowns", cooldowns.clone());
+        cooldowns
+    });
+
+    let user_cooldowns = args.cooldown_secs.map(|secs| {
+        let cooldowns = Arc::new(persisted_cooldowns(
+            store.as_ref(),
+            "user_cooldowns",
+            std::time::Duration::from_secs(secs),
+            MAX_TRACKED_USERS,
+        ));
+        sweeper.register("user cooldowns", cooldowns.clone());
+        cooldowns
+    });
+
+    let responses = (args.response_cache_ttl > 0).then(|| {
+        let responses = Arc::new(cache::ResponseCache::new(
+            std::time::Duration::from_secs(args.response_cache_ttl),
+            args.response_cache_size.max(1),
+        ));
+        sweeper.register("response cache", responses.clone());
+        responses
+    });
+
+    let disk_thumbnails = args.thumbnail_cache_dir.clone().map(|dir| {
+        let disk = Arc::new(
+            cache::DiskThumbnails::open(
+                dir,
+                args.thumbnail_cache_max_mb.map(|x| x * 1024 * 1024),
+                args.thumbnail_cache_max_age
+                    .map(std::time::Duration::from_secs),
+            )
+            .expect("failed to open thumbnail cache"),
+        );
+        disk.clone().spawn_cleanup(std::time::Duration::from_secs(
+            args.thumbnail_cache_cleanup_interval.max(1),
+        ));
+        disk
+    });
+
+    let seen_links = Arc::new(state::Cooldowns::new(max_age, MAX_TRACKED_VIDEOS));
+    sweeper.register("seen links", seen_links.clone());
+
+    let edits = Arc::new(edits::EditScheduler::new(std::time::Duration::from_millis(
+        args.min_edit_interval_ms,
+    )));
+    sweeper.register("edit schedule", edits.clone());
+
+    sweeper.spawn(std::time::Duration::from_secs(args.sweep_interval.max(1)));
+
+    // guilds are needed for the cache to know our permissions.
+    let mut intents =
+        GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+
+    // keeps the cached custom emoji up to date.
+    if !args.guild_emoji.is_empty() {
+        intents |= GatewayIntents::GUILD_EMOJIS_AND_STICKERS;
+    }
+
+    if args.allow_dm {
+        intents |= GatewayIntents::DIRECT_MESSAGES;
+    }
+
+    if args.retrigger_reactions {
+        intents |= GatewayIntents::GUILD_MESSAGE_REACTIONS;
+    }
+
+    log::info!("creating client");
+
+    #[cfg(feature = "metrics")]
+    let timings = {
+        let timings = Arc::new(metrics::HandlerTimings::new());
+        metrics::spawn_reporter(
+            timings.clone(),
+            std::time::Duration::from_secs(args.metrics_log_interval.max(1)),
+        );
+        timings
+    };
+
+    #[cfg(feature = "metrics")]
+    if let Some(port) = args.metrics_port {
+        let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+        exporter::spawn(
+            addr,
+            Arc::new(|path: &str| (path == "/metrics").then(|| ("200 OK", metrics::render()))),
+        )
+        .await
+        .expect("failed to serve metrics");
+        log::info!("serving prometheus metrics on http://{addr}/metrics.");
+    }
+
+    let health = Arc::new(health::Health::default());
+
+    if let Some(port) = args.health_port {
+        let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+        let route = health.clone();
+        exporter::spawn(addr, Arc::new(move |path: &str| route.route(path)))
+            .await
+            .expect("failed to serve health checks");
+        log::info!("serving health checks on http://{addr}/healthz.");
+    }
+
+    let activity = args
+        .summary_channel
+        .map(|_| Arc::new(summary::Activity::new()));
+
+    let mut cache_settings = serenity::cache::Settings::default();
+
+    if args.no_cache {
+        cache_settings.cache_guilds = false;
+        cache_settings.cache_channels = false;
+        cache_settings.cache_users = false;
+    }
+
+    let turns = Arc::new(schedule::FairGate::new(
+        args.max_concurrent_messages,
+        args.max_concurrent_messages_per_guild,
+    ));
+
+    let mut client = Client::builder(&args.token, intents)
+        .cache_settings(cache_settings)
+        .event_handler(Handler {
+            remove_embed: args.remove_embed,
+            suppress_embed_channels: args
+                .suppress_embed_channels
+                .iter()
+                .map(|x| ChannelId::from(*x))
+                .collect(),
+            allowed_channels: args
+                .allowed_channels
+                .iter()
+                .map(|x| ChannelId::from(*x))
+                .collect(),
+            allowed_guilds: args
+                .allowed_guilds
+                .iter()
+                .map(|x| GuildId::from(*x))
+                .collect(),
+            policy: decision::Policy {
+                thumbnail_mode: args.thumbnail_mode,
+                skip_matching_embeds: args.skip_matching_embeds,
+                min_video_duration: args.min_video_duration,
+                unknown_duration: args.unknown_duration,
+                title_selection: args.title_selection,
+                title_match: args.title_match,
+                locked_only: args.locked_only,
+                min_title_votes: args.min_title_votes,
+                min_thumbnail_votes: args.min_thumbnail_votes,
+                prefer_timestamped_thumbnails: args.prefer_timestamped_thumbnails,
+                random_frame_fallback: args.random_frame_fallback,
+                prefer_custom_titles: args.prefer_custom_titles,
+                reviewed_title: args.reviewed_title,
+                skip_recapitalization_only: args.skip_recapitalization_only,
+            },
+            allow_dm: args.allow_dm,
+            dearrow_page: args.link_dearrow_page.then_some(args.dearrow_page_url),
+            alt_text_format: Some(args.thumbnail_alt_text).filter(|x| !x.is_empty()),
+            max_thumbnail_bytes: args.max_thumbnail_bytes,
+            embed_footer: Some(args.embed_footer).filter(|x| !x.is_empty()),
+            embed_color: args.embed_color.map(|x| x.0),
+            show_sharer: args.show_sharer,
+            trust_polls: args.trust_polls,
+            trust_poll_margin: args.trust_poll_margin,
+            clips: args
+                .resolve_clips
+                .then(|| youtube::ClipResolver::new().expect("failed to create clip resolver")),
+            plain_text_fallback: args.plain_text_fallback,
+            emojis: emoji::GuildEmojis::new(&args.guild_emoji),
+            event_webhook: args
+                .event_webhook_url
+                .map(|url| events::EventWebhook::new(url).expect("failed to create event webhook")),
+            thumbnail_permits: Arc::new(tokio::sync::Semaphore::new(
+                args.max_thumbnail_concurrency.max(1),
+            )),
+            thumbnail_retry_delay: args
+                .thumbnail_retry_delay_ms
+                .map(std::time::Duration::from_millis),
+            background_thumbnails: args.background_thumbnails,
+            youtube_thumbnail_fallback: args.youtube_thumbnail_fallback,
+            embed_warnings,
+            denied_channels,
+            guild_command_limits,
+            user_command_limits,
+            replies,
+            video_cooldowns,
+            channel_video_cooldowns,
+            user_cooldowns,
+            health,
+            departures: guilds::Departures::new(std::time::Duration::from_secs(
+                args.guild_removal_grace,
+            )),
+            opt_outs: optout::OptOuts::load(args.optout_path.clone())
+                .expect("failed to load opt-outs"),
+            seen_links,
+            responses,
+            disk_thumbnails,
+            verify_video_exists: args.verify_video_exists,
+            show_fetch_time: args.show_fetch_time,
+            show_original_title: args.show_original_title,
+            turns: turns.clone(),
+            commands_registered: AtomicBool::new(false),
+            trust_tiers: trust::TrustTiers {
+                trusted_votes: args.trusted_votes,
+                community_votes: args.community_votes,
+            },
+            edits,
+            show_original_when_empty: args.show_original_when_empty,
+            embed_machine_tag: args.embed_machine_tag,
+            output_style: args.output_style,
+            reply_mode: args.reply_mode,
+            channel_reply_modes: args
+                .channel_reply_mode
+                .iter()
+                .map(|x| (x.channel_id, x.mode))
+                .collect(),
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_millis(args.request_timeout_ms))
+                .build()
+                .expect("failed to create http client"),
+            branding_api_base: args.branding_api_base,
+            thumbnail_api_base: args.thumbnail_api_base,
+            retry_attempts: args.retry_attempts.max(1),
+            disabled_commands: args
+                .disabled_commands
+                .iter()
+                .map(|x| (x.guild_id, x.command.clone()))
+                .collect(),
+            embed_limit: if args.batch_replies {
+                reply::EmbedLimit {
+                    max: args.max_embeds_per_message,
+                    overflow: args.embed_overflow,
+                }
+            } else {
+                reply::EmbedLimit {
+                    max: 1,
+                    overflow: reply::Overflow::Split,
+                }
+            },
+            max_scan_len: args.max_content_scan_len,
+            oversized_content: args.oversized_content,
+            owners: args.owner_ids.iter().map(|x| UserId::from(*x)).collect(),
+            maintenance: maintenance::Maintenance::default(),
+            show_channel: args.show_channel,
+            retrigger_reactions: args.retrigger_reactions,
+            embed_wait: std::time::Duration::from_millis(args.embed_wait_ms),
+            embed_settle: std::time::Duration::from_millis(args.embed_settle_ms),
+            activity: activity.clone(),
+            #[cfg(feature = "metrics")]
+            timings,
+        })
+        .await
         .expect("failed to create client");
 
+    if let (Some(activity), Some(channel_id)) = (activity, args.summary_channel) {
+        summary::spawn_poster(
+            activity,
+            client.http.clone(),
+            ChannelId::from(channel_id),
+            std::time::Duration::from_secs(args.summary_interval.max(1)),
+        );
+    }
+
     log::info!("DeArrowDiscordBot starting now!");
     log::info!("Thumbnail Mode: {:?}", args.thumbnail_mode);
     log::info!("Remove Embeds after Declickbaiting? {}", args.remove_embed);
+    if !args.suppress_embed_channels.is_empty() {
+        log::info!(
+            "Always removing embeds in channels {:?}",
+            args.suppress_embed_channels
+        );
+    }
+    if !args.allowed_guilds.is_empty() {
+        log::info!("Only declickbaiting in guilds {:?}", args.allowed_guilds);
+    }
+    if !args.allowed_channels.is_empty() {
+        log::info!(
+            "Only declickbaiting in channels {:?}",
+            args.allowed_channels
+        );
+    }
+    log::info!("Declickbaiting in DMs? {}", args.allow_dm);
+    if args.output_style == OutputStyle::Components {
+        log::info!(
+            "container components aren't supported yet, replying with the classic embed and link buttons."
+        );
+    }
+
+    let shard_manager = client.shard_manager.clone();
+    let shutdown = async move {
+        shutdown_signal().await;
+        log::info!("shutting down, no longer taking new events.");
+
+        // with no shard connected yet, shutdown_all leaves the client retrying forever, so
+        // stop waiting on it. otherwise it returns once the shards are closed.
+        if shard_manager.runners.lock().await.is_empty() {
+            return;
+        }
+
+        shard_manager.shutdown_all().await;
+        std::future::pending::<()>().await;
+    };
+
+    tokio::select! {
+        result = client.start() => {
+            if let Err(e) = result {
+                log::error!("{e:?}");
+            }
+        }
+        _ = shutdown => {}
+    }
+
+    // the shards are gone, but messages they delivered may still be mid-reply.
+    let grace = std::time::Duration::from_secs(args.shutdown_grace_secs);
+    if tokio::time::timeout(grace, turns.drained()).await.is_err() {
+        log::warn!(
+            "gave up on {} messages still being processed after {}s.",
+            turns.busy(),
+            grace.as_secs()
+        );
+    }
+
+    log::info!("shutdown complete.");
+
+    #[cfg(feature = "otlp")]
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        log::error!("failed to flush traces: {e:#?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response in the shape DeArrow's branding endpoint gives, with the fields we don't read.
+    const BRANDING: &str = r#"{
+        "titles": [
+            {
+                "title": "The Actual Title",
+                "original": false,
+                "votes": 3,
+                "locked": true,
+                "UUID": "0a1b2c3d-title",
+                "userID": "someone"
+            },
+            {
+                "title": "the actual title",
+                "original": true,
+                "votes": 0,
+                "locked": false,
+                "UUID": "4e5f6a7b-title"
+            }
+        ],
+        "thumbnails": [
+            {
+                "timestamp": 12.5,
+                "original": false,
+                "votes": 2,
+                "locked": false,
+                "UUID": "0a1b2c3d-thumb"
+            },
+            {
+                "original": true,
+                "votes": 1,
+                "locked": false,
+                "UUID": "4e5f6a7b-thumb"
+            }
+        ],
+        "randomTime": 123.25,
+        "videoDuration": 301.5
+    }"#;
+
+    #[test]
+    fn deserializes_branding() {
+        let branding: BrandingResponse = serde_json::from_str(BRANDING).unwrap();
+
+        assert_eq!(branding.titles.len(), 2);
+        assert_eq!(branding.titles[0].title, "The Actual Title");
+        assert_eq!(branding.titles[0].votes, 3);
+        assert!(branding.titles[0].locked);
+        assert_eq!(branding.titles[0].uuid, "0a1b2c3d-title");
+        assert!(!branding.titles[0].removed);
+        assert!(branding.titles[1].original);
+
+        assert_eq!(branding.thumbnails.len(), 2);
+        assert_eq!(branding.thumbnails[0].timestamp, Some(12.5));
+        assert_eq!(branding.thumbnails[0].votes, 2);
+        assert!(branding.thumbnails[1].original);
+        assert_eq!(branding.thumbnails[1].timestamp, None);
+
+        assert_eq!(branding.random_time, 123.25);
+        assert_eq!(branding.video_duration, Some(301.5));
+        assert_eq!(branding.service, None);
+    }
+
+    #[test]
+    fn deserializes_branding_without_a_duration() {
+        let branding: BrandingResponse = serde_json::from_str(
+            r#"{"titles": [], "thumbnails": [], "randomTime": 0.5, "videoDuration": null}"#,
+        )
+        .unwrap();
+
+        assert!(branding.titles.is_empty());
+        assert!(branding.thumbnails.is_empty());
+        assert_eq!(branding.video_duration, None);
+    }
+
+    #[test]
+    fn parses_embed_colors() {
+        assert_eq!(
+            "#00aaff".parse::<EmbedColor>().unwrap().0,
+            Colour::new(0x00aaff)
+        );
+        assert_eq!(
+            "FF0000".parse::<EmbedColor>().unwrap().0,
+            Colour::new(0xff0000)
+        );
+
+        for invalid in ["", "#00aaf", "#00aaffee", "#+0aaff", "blue"] {
+            assert!(invalid.parse::<EmbedColor>().is_err(), "{invalid}");
+        }
+    }
+
+    fn response(content_type: &str, body: &[u8]) -> reqwest::Response {
+        http::Response::builder()
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body.to_vec())
+            .unwrap()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn reads_thumbnails_of_the_allowed_types() {
+        let png = b"\x89PNG\r\n\x1a\nrest of the image";
+        let thumb = read_thumbnail(response("image/png", png)).await.unwrap();
+
+        assert_eq!(thumb.format, ImageFormat::Png);
+        assert_eq!(thumb.bytes, png);
+    }
+
+    #[tokio::test]
+    async fn rejects_thumbnails_that_arent_images() {
+        let page = b"<!DOCTYPE html><html><body>502 Bad Gateway</body></html>";
+
+        for content_type in ["text/html; charset=utf-8", "application/octet-stream", ""] {
+            assert!(
+                matches!(
+                    read_thumbnail(response(content_type, page)).await,
+                    Err(DeArrowError::Decode(_))
+                ),
+                "{content_type}"
+            );
+        }
+
+        // an error page passing itself off as an image.
+        assert!(matches!(
+            read_thumbnail(response("image/webp", page)).await,
+            Err(DeArrowError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn validating_adds_the_random_frame() {
+        let mut branding: BrandingResponse = serde_json::from_str(BRANDING).unwrap();
+        branding.validate("dQw4w9WgXcQ");
+
+        let frame = branding.random_frame.unwrap();
+        assert!(frame.random);
+        assert_eq!(frame.timestamp, Some(123.25));
+        assert_eq!(branding.titles.len(), 2);
+    }
+
+    #[test]
+    fn checks_the_branding_is_for_the_video_asked_about() {
+        let subject = |service: Option<&str>, video_id: Option<&str>| {
+            let mut branding: BrandingResponse = serde_json::from_str(BRANDING).unwrap();
+            branding.service = service.map(str::to_string);
+            branding.video_id = video_id.map(str::to_string);
+            branding.check_subject("dQw4w9WgXcQ").is_ok()
+        };
+
+        // plain DeArrow says neither.
+        assert!(subject(None, None));
+        assert!(subject(Some("YouTube"), Some("dQw4w9WgXcQ")));
+        assert!(subject(Some("youtube"), None));
+        assert!(!subject(Some("PeerTube"), Some("dQw4w9WgXcQ")));
+        assert!(!subject(None, Some("jNQXAC9IVRw")));
+        // ids are case sensitive.
+        assert!(!subject(Some("YouTube"), Some("DQW4W9WGXCQ")));
+    }
+
+    #[test]
+    fn validating_drops_duplicate_and_malformed_uuids() {
+        let mut branding: BrandingResponse = serde_json::from_str(
+            r#"{
+                "titles": [
+                    {"title": "First", "original": false, "votes": 1, "locked": false, "UUID": "a-1"},
+                    {"title": "Again", "original": false, "votes": 9, "locked": true, "UUID": "a-1"},
+                    {"title": "Empty", "original": false, "votes": 9, "locked": true, "UUID": ""},
+                    {"title": "Garbage", "original": false, "votes": 9, "locked": true, "UUID": "<script>"},
+                    {"title": "Second", "original": true, "votes": 0, "locked": false, "UUID": "b2"}
+                ],
+                "thumbnails": [
+                    {"timestamp": 1.0, "original": false, "votes": 1, "locked": false, "UUID": "c"},
+                    {"timestamp": 2.0, "original": false, "votes": 9, "locked": true, "UUID": "c"},
+                    {"timestamp": 3.0, "original": false, "votes": 9, "locked": true, "UUID": "d e"}
+                ],
+                "randomTime": 0.5,
+                "videoDuration": null
+            }"#,
+        )
+        .unwrap();
+
+        branding.validate("dQw4w9WgXcQ");
+
+        let titles: Vec<_> = branding.titles.iter().map(|x| x.title.as_str()).collect();
+        assert_eq!(titles, ["First", "Second"]);
+
+        let thumbnails: Vec<_> = branding.thumbnails.iter().map(|x| x.timestamp).collect();
+        assert_eq!(thumbnails, [Some(1.0)]);
+    }
+
+    #[test]
+    fn skips_removed_and_shadow_hidden_submissions() {
+        let branding: BrandingResponse = serde_json::from_str(
+            r#"{
+                "titles": [
+                    {"title": "Removed", "original": false, "votes": 9, "locked": true, "UUID": "a", "removed": true},
+                    {"title": "Hidden", "original": false, "votes": 5, "locked": false, "UUID": "b", "shadowHidden": true},
+                    {"title": "Visible", "original": false, "votes": 1, "locked": false, "UUID": "c", "removed": false}
+                ],
+                "thumbnails": [
+                    {"timestamp": 1.0, "original": false, "votes": 9, "locked": true, "UUID": "d", "shadowHidden": true},
+                    {"timestamp": 2.0, "original": false, "votes": 1, "locked": false, "UUID": "e"}
+                ],
+                "randomTime": 0.5,
+                "videoDuration": null
+            }"#,
+        )
+        .unwrap();
+
+        assert!(branding.titles[0].removed && branding.titles[1].shadow_hidden);
+        assert_eq!(branding.title().unwrap().title, "Visible");
+        assert_eq!(
+            branding
+                .select_title(TitleSelection::MostVotes)
+                .unwrap()
+                .title,
+            "Visible"
+        );
+        assert_eq!(
+            branding
+                .select_title(TitleSelection::LockedFirst)
+                .unwrap()
+                .title,
+            "Visible"
+        );
+        assert_eq!(branding.thumbnail().unwrap().timestamp, Some(2.0));
+        assert_eq!(branding.visible_thumbnails().count(), 1);
+    }
+
+    #[test]
+    fn normalizes_clickbaity_titles() {
+        for (clickbait, clean) in [
+            ("🔥 INSANE!!!", "insane"),
+            ("You WON'T Believe This...", "you won t believe this"),
+            ("  Spaces   and\ttabs ", "spaces and tabs"),
+            ("Ünïcödé ÉXTRÊME?!", "ünïcödé éxtrême"),
+            ("🔥🔥🔥", ""),
+        ] {
+            assert_eq!(normalize_title(clickbait), clean, "{clickbait}");
+        }
+    }
+
+    #[test]
+    fn matches_titles_loosely_or_exactly() {
+        for (a, b, exact, loose) in [
+            ("Insane", "Insane", true, true),
+            (" Insane ", "Insane", true, true),
+            ("🔥 INSANE!!!", "Insane", false, true),
+            (
+                "I tried it - it WORKED",
+                "i tried it, it worked",
+                false,
+                true,
+            ),
+            ("Insane", "Insane Drop", false, false),
+            ("The 10 Best", "The 11 Best", false, false),
+        ] {
+            assert_eq!(TitleMatch::Exact.matches(a, b), exact, "{a} / {b}");
+            assert_eq!(TitleMatch::Loose.matches(a, b), loose, "{a} / {b}");
+        }
+    }
+
+    #[test]
+    fn waits_for_embeds_or_for_them_to_settle() {
+        let timeout = std::time::Duration::from_millis(3000);
+        let settle = std::time::Duration::from_millis(500);
+
+        // embedded already, so only wait for them to settle. an update in the meantime can't
+        // take them away.
+        assert_eq!(embed_wait(true, timeout, settle), settle);
+        assert!(embedded_after(true, Some(false)));
+        assert!(embedded_after(true, None));
+
+        // not embedded yet, so wait for the update that embeds it.
+        assert_eq!(embed_wait(false, timeout, settle), timeout);
+        assert!(embedded_after(false, Some(true)));
+        assert!(!embedded_after(false, Some(false)));
+    }
+
+    #[test]
+    fn leaves_the_message_alone_when_discord_never_embeds_it() {
+        // the wait timed out without any update, or there was none to wait for.
+        assert!(!embedded_after(false, None));
+        assert!(embed_wait(false, std::time::Duration::ZERO, std::time::Duration::ZERO).is_zero());
+    }
+
+    #[test]
+    fn bounds_how_much_of_a_message_is_scanned() {
+        let link = "https://youtu.be/dQw4w9WgXcQ ";
+        let wall = link.repeat(1000);
+
+        assert_eq!(OversizedContent::Skip.scannable(link, 64), Some(link));
+        assert_eq!(OversizedContent::Skip.scannable(&wall, 64), None);
+
+        let prefix = OversizedContent::Prefix.scannable(&wall, 64).unwrap();
+        assert_eq!(prefix.len(), 64);
+        assert_eq!(youtube::find_links(prefix).len(), 1);
+
+        // never cut a character in half.
+        assert_eq!(OversizedContent::Prefix.scannable("aé", 2), Some("a"));
+    }
+
+    #[test]
+    fn finds_the_thumbnail_in_a_json_envelope() {
+        let source = |json: &str| {
+            serde_json::from_str::<ThumbnailEnvelope>(json)
+                .unwrap()
+                .source()
+        };
+        let data = |json: &str| match source(json) {
+            Ok(ThumbnailSource::Data(bytes)) => Some(bytes),
+            _ => None,
+        };
+        let url = |json: &str| match source(json) {
+            Ok(ThumbnailSource::Url(url)) => Some(url),
+            _ => None,
+        };
+
+        const JPEG: &[u8] = &[0xFF, 0xD8, 0xFF];
+
+        assert_eq!(
+            data(r#"{"data": "data:image/jpeg;base64,/9j/"}"#).unwrap(),
+            JPEG
+        );
+        assert_eq!(data(r#"{"image": "/9j/"}"#).unwrap(), JPEG);
+        assert_eq!(data(r#"{"base64": " /9j/\n"}"#).unwrap(), JPEG);
+        // the image wins over a link to it.
+        assert_eq!(data(r#"{"url": "/a.png", "data": "/9j/"}"#).unwrap(), JPEG);
+
+        assert_eq!(url(r#"{"url": "/a.png"}"#).unwrap(), "/a.png");
+        assert_eq!(
+            url(r#"{"imageUrl": "https://example.com/a.png"}"#).unwrap(),
+            "https://example.com/a.png"
+        );
+        assert_eq!(url(r#"{"image_url": "a.png"}"#).unwrap(), "a.png");
 
-    if let Err(e) = client.start().await {
-        log::error!("{e:?}");
+        assert!(source(r#"{}"#).is_err());
+        assert!(source(r#"{"data": "not base64!"}"#).is_err());
     }
 }
@@ -0,0 +1,52 @@
+//! Pausing auto-replies for a while, e.g. during a DeArrow API migration.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Whether the bot is in maintenance, and until when.
+#[derive(Default)]
+pub struct Maintenance {
+    /// `Some(None)` while in maintenance until turned off, `Some(Some(until))` if it ends on
+    /// its own.
+    state: Mutex<Option<Option<Instant>>>,
+}
+
+impl Maintenance {
+    pub fn start(&self, until: Option<Instant>) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = Some(until);
+        }
+
+        match until {
+            Some(until) => log::info!(
+                "maintenance started, ending in {} minutes.",
+                until.saturating_duration_since(Instant::now()).as_secs() / 60
+            ),
+            None => log::info!("maintenance started."),
+        }
+    }
+
+    pub fn end(&self) {
+        if let Ok(mut state) = self.state.lock()
+            && state.take().is_some()
+        {
+            log::info!("maintenance ended.");
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+
+        match *state {
+            Some(Some(until)) if until <= Instant::now() => {
+                *state = None;
+                log::info!("maintenance ended, its time ran out.");
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
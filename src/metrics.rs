@@ -0,0 +1,351 @@
+//! Handler instrumentation. Only compiled with the `metrics` feature.
+//!
+//! Besides the periodic log reports, everything here is also kept as running totals for the
+//! Prometheus exporter in [`crate::exporter`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+use crate::error::DeArrowError;
+
+/// End-to-end `message` processing times, from detecting a video ID to the reply being sent.
+pub struct HandlerTimings {
+    histogram: Mutex<Histogram<u64>>,
+}
+
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl HandlerTimings {
+    pub fn new() -> Self {
+        // microsecond resolution, anything above a minute is clamped.
+        let histogram = Histogram::new_with_bounds(1, 60_000_000, 3)
+            .expect("failed to create timing histogram");
+
+        Self {
+            histogram: Mutex::new(histogram),
+        }
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+
+        if let Ok(mut histogram) = self.histogram.lock() {
+            histogram.saturating_record(micros.max(1));
+        }
+    }
+
+    /// Returns the percentiles recorded since the last call and starts a fresh window.
+    pub fn take(&self) -> Option<Percentiles> {
+        let mut histogram = self.histogram.lock().ok()?;
+
+        if histogram.is_empty() {
+            return None;
+        }
+
+        let percentiles = Percentiles {
+            count: histogram.len(),
+            p50: Duration::from_micros(histogram.value_at_quantile(0.50)),
+            p95: Duration::from_micros(histogram.value_at_quantile(0.95)),
+            p99: Duration::from_micros(histogram.value_at_quantile(0.99)),
+        };
+
+        histogram.reset();
+
+        Some(percentiles)
+    }
+}
+
+pub static BRANDING: EndpointCounters = EndpointCounters::new("branding");
+pub static THUMBNAIL: EndpointCounters = EndpointCounters::new("thumbnail");
+
+/// How many requests to an endpoint ended which way.
+struct Outcomes {
+    ok: AtomicU64,
+    network: AtomicU64,
+    client: AtomicU64,
+    server: AtomicU64,
+    parse: AtomicU64,
+}
+
+impl Outcomes {
+    const fn new() -> Self {
+        Self {
+            ok: AtomicU64::new(0),
+            network: AtomicU64::new(0),
+            client: AtomicU64::new(0),
+            server: AtomicU64::new(0),
+            parse: AtomicU64::new(0),
+        }
+    }
+
+    fn counter<T>(&self, result: &Result<T, DeArrowError>) -> &AtomicU64 {
+        match result {
+            Ok(_) => &self.ok,
+            Err(DeArrowError::NotFound) => &self.client,
+            Err(DeArrowError::Http(status)) if status.is_server_error() => &self.server,
+            Err(DeArrowError::Http(_)) => &self.client,
+            Err(DeArrowError::Network(_)) => &self.network,
+            // only ever found after the request was counted, but it's about what came back.
+            Err(DeArrowError::Decode(_) | DeArrowError::TooLarge) => &self.parse,
+        }
+    }
+
+    fn each(&self) -> [(&'static str, &AtomicU64); 5] {
+        [
+            ("ok", &self.ok),
+            ("network", &self.network),
+            ("client", &self.client),
+            ("server", &self.server),
+            ("parse", &self.parse),
+        ]
+    }
+}
+
+/// Outcomes of the requests to one DeArrow endpoint, to tell which one is degraded.
+pub struct EndpointCounters {
+    name: &'static str,
+    /// Since the last report.
+    window: Outcomes,
+    total: Outcomes,
+}
+
+impl EndpointCounters {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            window: Outcomes::new(),
+            total: Outcomes::new(),
+        }
+    }
+
+    pub fn record<T>(&self, result: &Result<T, DeArrowError>) {
+        self.window.counter(result).fetch_add(1, Ordering::Relaxed);
+        self.total.counter(result).fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Logs the counts since the last call and resets them.
+    fn report(&self) {
+        let take = |x: &AtomicU64| x.swap(0, Ordering::Relaxed);
+        let window = &self.window;
+
+        let ok = take(&window.ok);
+        let (network, client, server, parse) = (
+            take(&window.network),
+            take(&window.client),
+            take(&window.server),
+            take(&window.parse),
+        );
+        let failed = network + client + server + parse;
+
+        if ok + failed == 0 {
+            return;
+        }
+
+        log::info!(
+            "{} requests: {ok} ok, {failed} failed (network {network}, 4xx {client}, 5xx {server}, parse {parse})",
+            self.name
+        );
+    }
+}
+
+pub static MESSAGES: Counter = Counter::new();
+pub static DECLICKBAITS: Counter = Counter::new();
+
+pub static BRANDING_CACHE: CacheCounters = CacheCounters::new("branding");
+pub static THUMBNAIL_CACHE: CacheCounters = CacheCounters::new("thumbnail");
+
+pub static BRANDING_LATENCY: LatencyHistogram = LatencyHistogram::new();
+
+/// A running total.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Lookups in one of the response caches.
+pub struct CacheCounters {
+    name: &'static str,
+    hits: Counter,
+    misses: Counter,
+}
+
+impl CacheCounters {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            hits: Counter::new(),
+            misses: Counter::new(),
+        }
+    }
+
+    pub fn record(&self, hit: bool) {
+        if hit {
+            self.hits.increment();
+        } else {
+            self.misses.increment();
+        }
+    }
+}
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Request latencies in Prometheus' histogram shape, with cumulative buckets.
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(
+            elapsed.as_micros().min(u64::MAX as u128) as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Renders all running totals in the Prometheus text exposition format.
+pub fn render() -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let load = |x: &AtomicU64| x.load(Ordering::Relaxed);
+
+    let _ = writeln!(
+        out,
+        "# HELP dearrow_messages_processed_total Messages looked through for video links."
+    );
+    let _ = writeln!(out, "# TYPE dearrow_messages_processed_total counter");
+    let _ = writeln!(out, "dearrow_messages_processed_total {}", MESSAGES.get());
+
+    let _ = writeln!(
+        out,
+        "# HELP dearrow_declickbaits_total Videos replied to with their de-arrowed branding."
+    );
+    let _ = writeln!(out, "# TYPE dearrow_declickbaits_total counter");
+    let _ = writeln!(out, "dearrow_declickbaits_total {}", DECLICKBAITS.get());
+
+    let _ = writeln!(
+        out,
+        "# HELP dearrow_api_requests_total Requests to the DeArrow API, by endpoint and outcome."
+    );
+    let _ = writeln!(out, "# TYPE dearrow_api_requests_total counter");
+    for endpoint in [&BRANDING, &THUMBNAIL] {
+        for (outcome, counter) in endpoint.total.each() {
+            let _ = writeln!(
+                out,
+                "dearrow_api_requests_total{{endpoint=\"{}\",outcome=\"{outcome}\"}} {}",
+                endpoint.name,
+                load(counter)
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP dearrow_cache_lookups_total Response cache lookups, by cache and result."
+    );
+    let _ = writeln!(out, "# TYPE dearrow_cache_lookups_total counter");
+    for cache in [&BRANDING_CACHE, &THUMBNAIL_CACHE] {
+        for (result, counter) in [("hit", &cache.hits), ("miss", &cache.misses)] {
+            let _ = writeln!(
+                out,
+                "dearrow_cache_lookups_total{{cache=\"{}\",result=\"{result}\"}} {}",
+                cache.name,
+                counter.get()
+            );
+        }
+    }
+
+    let latency = &BRANDING_LATENCY;
+    let _ = writeln!(
+        out,
+        "# HELP dearrow_branding_fetch_seconds How long fetching branding from DeArrow took."
+    );
+    let _ = writeln!(out, "# TYPE dearrow_branding_fetch_seconds histogram");
+    for (bucket, bound) in latency.buckets.iter().zip(LATENCY_BUCKETS) {
+        let _ = writeln!(
+            out,
+            "dearrow_branding_fetch_seconds_bucket{{le=\"{bound}\"}} {}",
+            load(bucket)
+        );
+    }
+    let count = load(&latency.count);
+    let _ = writeln!(
+        out,
+        "dearrow_branding_fetch_seconds_bucket{{le=\"+Inf\"}} {count}"
+    );
+    let _ = writeln!(
+        out,
+        "dearrow_branding_fetch_seconds_sum {}",
+        load(&latency.sum_micros) as f64 / 1_000_000.0
+    );
+    let _ = writeln!(out, "dearrow_branding_fetch_seconds_count {count}");
+
+    out
+}
+
+/// Periodically logs the handler timing percentiles and the DeArrow endpoint error rates.
+pub fn spawn_reporter(timings: Arc<HandlerTimings>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        // the first tick completes immediately.
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            match timings.take() {
+                Some(p) => log::info!(
+                    "handler timings over {} replies: p50 {:?}, p95 {:?}, p99 {:?}",
+                    p.count,
+                    p.p50,
+                    p.p95,
+                    p.p99
+                ),
+                None => log::debug!("no replies sent since the last timing report."),
+            }
+
+            BRANDING.report();
+            THUMBNAIL.report();
+        }
+    });
+}
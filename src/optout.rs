@@ -0,0 +1,71 @@
+//! Users who asked with /dearrow-optout not to have their own messages de-clickbaited.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use serenity::all::UserId;
+
+/// The opted-out users, written to a JSON file of their IDs on every change if there is one.
+pub struct OptOuts {
+    users: Mutex<HashSet<UserId>>,
+    path: Option<PathBuf>,
+}
+
+impl OptOuts {
+    /// Loads the users from `path`, which may not exist yet. In-memory only without one.
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let users = match path {
+            Some(ref path) if path.exists() => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("could not read {}", path.display()))?;
+
+                serde_json::from_str::<Vec<u64>>(&text)
+                    .with_context(|| format!("{} is malformed", path.display()))?
+                    .into_iter()
+                    .filter(|x| *x != 0)
+                    .map(UserId::new)
+                    .collect()
+            }
+            _ => HashSet::new(),
+        };
+
+        Ok(Self {
+            users: Mutex::new(users),
+            path,
+        })
+    }
+
+    pub fn contains(&self, user_id: UserId) -> bool {
+        self.users
+            .lock()
+            .is_ok_and(|users| users.contains(&user_id))
+    }
+
+    /// Opts the user out, or back in. Returns whether that changed anything.
+    pub fn set(&self, user_id: UserId, opted_out: bool) -> anyhow::Result<bool> {
+        let Ok(mut users) = self.users.lock() else {
+            anyhow::bail!("opt-outs are poisoned");
+        };
+
+        let changed = if opted_out {
+            users.insert(user_id)
+        } else {
+            users.remove(&user_id)
+        };
+
+        if changed && let Some(ref path) = self.path {
+            let mut ids = users.iter().map(|x| x.get()).collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            // written next to it first, so a crash midway doesn't lose everyone's choice.
+            let temp = path.with_extension("tmp");
+            std::fs::write(&temp, serde_json::to_string(&ids)?)
+                .and_then(|()| std::fs::rename(&temp, path))
+                .with_context(|| format!("could not write {}", path.display()))?;
+        }
+
+        Ok(changed)
+    }
+}
@@ -0,0 +1,59 @@
+//! What the bot is allowed to do where, as far as the cache knows.
+
+use serenity::all::{Cache, ChannelId, GuildId, Member, Permissions};
+
+/// The bot's permissions in a guild channel or thread. `None` if the cache doesn't know the
+/// guild, channel, or the bot's own member yet.
+pub fn in_channel(cache: &Cache, guild_id: GuildId, channel_id: ChannelId) -> Option<Permissions> {
+    let bot_id = cache.current_user().id;
+    let member = cache.guild(guild_id)?.members.get(&bot_id)?.clone();
+
+    member_in_channel(cache, guild_id, channel_id, &member)
+}
+
+/// `member`'s permissions in a guild channel or thread. `None` if the cache doesn't know the
+/// guild or channel yet.
+pub fn member_in_channel(
+    cache: &Cache,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    member: &Member,
+) -> Option<Permissions> {
+    let guild = cache.guild(guild_id)?;
+
+    let channel = match guild.channels.get(&channel_id) {
+        Some(channel) => channel,
+        // threads inherit the permission overwrites of the channel they're in.
+        None => {
+            let thread = guild.threads.iter().find(|x| x.id == channel_id)?;
+            guild.channels.get(&thread.parent_id?)?
+        }
+    };
+
+    Some(guild.user_permissions_in(channel, member))
+}
+
+/// What the bot lacks to reply in a guild channel or thread, empty if nothing. Embed Links
+/// only counts if `embeds` are needed. `None` if the cache doesn't know the guild, channel, or
+/// the bot's own member yet.
+pub fn missing_to_reply(
+    cache: &Cache,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    embeds: bool,
+) -> Option<Permissions> {
+    let is_thread = !cache.guild(guild_id)?.channels.contains_key(&channel_id);
+
+    let mut needed = Permissions::VIEW_CHANNEL
+        | if is_thread {
+            Permissions::SEND_MESSAGES_IN_THREADS
+        } else {
+            Permissions::SEND_MESSAGES
+        };
+
+    if embeds {
+        needed |= Permissions::EMBED_LINKS;
+    }
+
+    Some(needed - in_channel(cache, guild_id, channel_id)?)
+}
@@ -0,0 +1,124 @@
+//! Shrinking thumbnails that are too large for Discord to take as an attachment.
+
+use image::DynamicImage;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+
+use crate::error::DeArrowError;
+use crate::{ImageFormat, Thumbnail};
+
+/// Good enough for a thumbnail, and far smaller than what DeArrow sends.
+const JPEG_QUALITY: u8 = 80;
+
+/// Past this, a thumbnail isn't worth showing anymore.
+const MIN_WIDTH: u32 = 160;
+
+/// `thumb` if it fits in `max_bytes`, otherwise re-encoded as a JPEG and scaled down until it
+/// does.
+pub async fn fit(thumb: Thumbnail, max_bytes: usize) -> Result<Thumbnail, DeArrowError> {
+    if thumb.bytes.len() <= max_bytes {
+        return Ok(thumb);
+    }
+
+    tokio::task::spawn_blocking(move || shrink(&thumb, max_bytes))
+        .await
+        .map_err(anyhow::Error::from)?
+}
+
+fn shrink(thumb: &Thumbnail, max_bytes: usize) -> Result<Thumbnail, DeArrowError> {
+    let image = image::load_from_memory(&thumb.bytes)
+        .map_err(|e| anyhow::Error::from(e).context("thumbnail isn't a decodable image"))?;
+
+    // JPEG can't do transparency, and thumbnails don't need it.
+    let mut image = DynamicImage::ImageRgb8(image.to_rgb8());
+
+    loop {
+        let mut bytes = Vec::new();
+        JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY)
+            .encode_image(&image)
+            .map_err(anyhow::Error::from)?;
+
+        if bytes.len() <= max_bytes {
+            log::info!(
+                "recompressed a {} byte thumbnail to {} bytes at {}x{}.",
+                thumb.bytes.len(),
+                bytes.len(),
+                image.width(),
+                image.height()
+            );
+
+            return Ok(Thumbnail {
+                bytes,
+                format: ImageFormat::Jpeg,
+            });
+        }
+
+        if image.width() <= MIN_WIDTH {
+            return Err(DeArrowError::TooLarge);
+        }
+
+        image = image.resize(
+            image.width() * 3 / 4,
+            image.height() * 3 / 4,
+            FilterType::Triangle,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Thumbnail {
+        // noise, so it doesn't compress down to nothing.
+        let image = image::RgbImage::from_fn(width, height, |x, y| {
+            let n = (x.wrapping_mul(2_654_435_761) ^ y.wrapping_mul(40_503)) as u8;
+            image::Rgb([n, n.wrapping_mul(7), n.wrapping_mul(13)])
+        });
+
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+
+        Thumbnail {
+            bytes: bytes.into_inner(),
+            format: ImageFormat::Png,
+        }
+    }
+
+    #[tokio::test]
+    async fn leaves_small_thumbnails_alone() {
+        let thumb = png(32, 18);
+        let len = thumb.bytes.len();
+
+        assert_eq!(fit(thumb, len).await.unwrap().format, ImageFormat::Png);
+    }
+
+    #[test]
+    fn shrinks_large_thumbnails_below_the_limit() {
+        let thumb = png(640, 360);
+        let max = thumb.bytes.len() / 10;
+
+        let fitted = shrink(&thumb, max).unwrap();
+        assert!(fitted.bytes.len() <= max);
+        assert_eq!(fitted.format, ImageFormat::Jpeg);
+        assert_eq!(ImageFormat::sniff(&fitted.bytes), Some(ImageFormat::Jpeg));
+    }
+
+    #[test]
+    fn gives_up_on_impossible_limits() {
+        assert!(matches!(
+            shrink(&png(640, 360), 100),
+            Err(DeArrowError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_what_isnt_an_image() {
+        let thumb = Thumbnail {
+            bytes: b"RIFF\0\0\0\0WEBPgarbage".to_vec(),
+            format: ImageFormat::Webp,
+        };
+
+        assert!(matches!(shrink(&thumb, 1), Err(DeArrowError::Decode(_))));
+    }
+}
@@ -0,0 +1,672 @@
+//! Sending the de-clickbaited replies.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serenity::all::{
+    ChannelId, CreateButton, CreateEmbed, CreateMessage, CreatePoll, CreatePollAnswer, Http,
+    HttpError, Message,
+};
+
+use crate::Thumbnail;
+
+/// Discord's JSON error code for "Request entity too large".
+const ENTITY_TOO_LARGE: isize = 40005;
+
+/// Discord's JSON error codes for "Missing access" and "Missing permissions".
+const MISSING_ACCESS: isize = 50001;
+const MISSING_PERMISSIONS: isize = 50013;
+
+/// Discord allows 5 action rows of 5 buttons each.
+const MAX_BUTTONS: usize = 25;
+
+/// Discord allows 10 embeds per message.
+pub const MAX_EMBEDS: usize = 10;
+
+/// How often a rate limited message is retried before giving up on it.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// How replies relate to the message with the link.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ReplyMode {
+    /// A reply pointing back at the message.
+    Reference,
+    /// A message of its own.
+    Standalone,
+}
+
+impl std::fmt::Display for ReplyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for ReplyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reference" => Ok(ReplyMode::Reference),
+            "standalone" => Ok(ReplyMode::Standalone),
+            _ => Err(anyhow::anyhow!("can't parse reply mode")),
+        }
+    }
+}
+
+/// The message a reply to `msg` in `mode` should point back at, if any.
+pub fn reference(msg: &Message, mode: ReplyMode) -> Option<&Message> {
+    // in DMs there is only the one conversation, no need to point back at the link.
+    msg.guild_id?;
+
+    match mode {
+        ReplyMode::Reference => Some(msg),
+        ReplyMode::Standalone => None,
+    }
+}
+
+/// A `<channel id>:<reply mode>` override, as passed on the command line.
+#[derive(Clone, Debug)]
+pub struct ChannelReplyMode {
+    pub channel_id: ChannelId,
+    pub mode: ReplyMode,
+}
+
+impl FromStr for ChannelReplyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((channel_id, mode)) = s.split_once(':') else {
+            anyhow::bail!("expected <channel id>:<reply mode>");
+        };
+
+        Ok(ChannelReplyMode {
+            channel_id: channel_id.trim().parse::<std::num::NonZeroU64>()?.into(),
+            mode: mode.trim().parse()?,
+        })
+    }
+}
+
+/// What happens to embeds beyond the per-message limit.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Overflow {
+    /// They go into further messages.
+    Split,
+    /// They are left out, with a note saying how many.
+    Drop,
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "split" => Ok(Overflow::Split),
+            "drop" => Ok(Overflow::Drop),
+            _ => Err(anyhow::anyhow!("can't parse overflow")),
+        }
+    }
+}
+
+/// How many embeds go into one message, and what happens to the rest.
+#[derive(Clone, Copy, Debug)]
+pub struct EmbedLimit {
+    pub max: usize,
+    pub overflow: Overflow,
+}
+
+/// The embed for one video, along with the thumbnail it shows.
+#[derive(Clone)]
+pub struct ReplyPart {
+    pub embed: CreateEmbed,
+    pub thumbnail: Option<Thumbnail>,
+    /// Describes the thumbnail to screen readers.
+    pub alt_text: Option<String>,
+    pub buttons: Vec<CreateButton>,
+}
+
+/// Builds one message out of `parts`, each tagged with its index in the whole reply.
+fn build_message(
+    reference: Option<&Message>,
+    parts: &[(usize, ReplyPart)],
+    note: Option<&str>,
+) -> CreateMessage {
+    let mut message = CreateMessage::new();
+
+    if let Some(note) = note {
+        message = message.content(note);
+    }
+
+    for (n, (_, part)) in parts.iter().enumerate() {
+        message = match part.thumbnail {
+            Some(ref thumb) => message
+                .add_embed(part.embed.clone().attachment(thumb.filename(n)))
+                .add_file(thumb.clone().into_attachment(n, part.alt_text.as_deref())),
+            None => message.add_embed(part.embed.clone()),
+        };
+    }
+
+    for button in parts.iter().flat_map(|(_, x)| &x.buttons).take(MAX_BUTTONS) {
+        message = message.button(button.clone());
+    }
+
+    match reference {
+        Some(reference) => message.reference_message(reference),
+        None => message,
+    }
+}
+
+enum Failure {
+    TooLarge(serenity::Error),
+    RateLimited(serenity::Error),
+    MissingPermissions,
+    Other(serenity::Error),
+}
+
+impl From<serenity::Error> for Failure {
+    fn from(e: serenity::Error) -> Self {
+        let serenity::Error::Http(HttpError::UnsuccessfulRequest(ref res)) = e else {
+            return Failure::Other(e);
+        };
+
+        let (status, code) = (res.status_code.as_u16(), res.error.code);
+        Failure::classify(status, code, e)
+    }
+}
+
+impl Failure {
+    /// What Discord refusing with `status` and JSON error `code` is about.
+    fn classify(status: u16, code: isize, e: serenity::Error) -> Self {
+        match (status, code) {
+            (413, _) | (_, ENTITY_TOO_LARGE) => Failure::TooLarge(e),
+            (429, _) => Failure::RateLimited(e),
+            (403, _) | (_, MISSING_ACCESS | MISSING_PERMISSIONS) => Failure::MissingPermissions,
+            _ => Failure::Other(e),
+        }
+    }
+}
+
+/// Sends `message`, retrying it if we got rate limited.
+///
+/// serenity's ratelimiter already waits out the limits it knows about and doesn't hand us the
+/// `retry_after` of the ones it didn't, so the retries just back off exponentially.
+async fn deliver(
+    http: &Http,
+    channel_id: ChannelId,
+    message: CreateMessage,
+) -> Result<Message, Failure> {
+    let mut attempt = 0;
+
+    loop {
+        match channel_id.send_message(http, message.clone()).await {
+            Ok(message) => return Ok(message),
+            Err(e) => match Failure::from(e) {
+                Failure::RateLimited(_) if attempt < MAX_RATE_LIMIT_RETRIES => {
+                    let delay = Duration::from_secs(1 << attempt);
+                    log::warn!(
+                        "rate limited in {channel_id}, retrying in {}s.",
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                failure => return Err(failure),
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Sent {
+    /// The messages that were sent, in order.
+    pub messages: Vec<Message>,
+    /// The parts that were delivered, as (part, message) index pairs.
+    delivered: Vec<(usize, usize)>,
+    /// Whether Discord refused because we lack access or permissions in the channel.
+    pub denied: bool,
+}
+
+impl Sent {
+    /// Whether anything was delivered.
+    pub fn any(&self) -> bool {
+        !self.delivered.is_empty()
+    }
+
+    /// Whether the part at `index` was delivered.
+    pub fn delivered(&self, index: usize) -> bool {
+        self.delivered.iter().any(|(part, _)| *part == index)
+    }
+
+    /// The message the part at `index` was delivered in, if it's the only part in there.
+    pub fn alone_in(&self, index: usize) -> Option<&Message> {
+        let (_, message) = self.delivered.iter().find(|(part, _)| *part == index)?;
+
+        if self.delivered.iter().filter(|(_, x)| x == message).count() > 1 {
+            return None;
+        }
+
+        self.messages.get(*message)
+    }
+
+    /// Logs the failure, unless it's a lack of permissions that the caller deals with.
+    fn fail(&mut self, failure: Failure) {
+        match failure {
+            Failure::MissingPermissions => self.denied = true,
+            Failure::TooLarge(e) | Failure::RateLimited(e) | Failure::Other(e) => {
+                log::error!("could not send message: {e:#?}")
+            }
+        }
+    }
+
+    fn single(result: Result<Message, Failure>) -> Self {
+        let mut sent = Sent::default();
+
+        match result {
+            Ok(message) => {
+                sent.delivered.push((0, 0));
+                sent.messages.push(message);
+            }
+            Err(failure) => sent.fail(failure),
+        }
+
+        sent
+    }
+}
+
+/// Sends the parts in as few messages as `limit` allows, or drops the ones beyond it. If
+/// Discord rejects a combined payload as too large, its parts are split across multiple
+/// messages in their original order, and a part that is too large on its own is sent without
+/// its thumbnail.
+#[tracing::instrument(skip_all, fields(channel_id = channel_id.get(), parts = parts.len()))]
+pub async fn send(
+    http: &Http,
+    channel_id: ChannelId,
+    reference: Option<&Message>,
+    parts: Vec<ReplyPart>,
+    limit: EmbedLimit,
+) -> Sent {
+    send_with(
+        |message| deliver(http, channel_id, message),
+        reference,
+        parts,
+        limit,
+    )
+    .await
+}
+
+/// [`send`], with `deliver` sending the messages.
+async fn send_with<F, Fut>(
+    mut deliver: F,
+    reference: Option<&Message>,
+    mut parts: Vec<ReplyPart>,
+    limit: EmbedLimit,
+) -> Sent
+where
+    F: FnMut(CreateMessage) -> Fut,
+    Fut: Future<Output = Result<Message, Failure>>,
+{
+    let max = limit.max.clamp(1, MAX_EMBEDS);
+
+    let note = if limit.overflow == Overflow::Drop && parts.len() > max {
+        let dropped = parts.len() - max;
+        log::info!("leaving out {dropped} embeds beyond the limit of {max}.");
+        parts.truncate(max);
+        Some(format!("+{dropped} more"))
+    } else {
+        None
+    };
+
+    // the note goes with whichever message ends up carrying the last part.
+    let last = parts.len().saturating_sub(1);
+
+    let indexed = parts.into_iter().enumerate().collect::<Vec<_>>();
+    let mut queue = indexed
+        .chunks(max)
+        .map(|x| x.to_vec())
+        .collect::<VecDeque<_>>();
+    let mut sent = Sent::default();
+
+    while let Some(mut chunk) = queue.pop_front() {
+        if chunk.is_empty() {
+            continue;
+        }
+
+        let note = note
+            .as_deref()
+            .filter(|_| chunk.iter().any(|(part, _)| *part == last));
+
+        let e = match deliver(build_message(reference, &chunk, note)).await {
+            Ok(message) => {
+                let index = sent.messages.len();
+                sent.delivered
+                    .extend(chunk.iter().map(|(part, _)| (*part, index)));
+                sent.messages.push(message);
+                continue;
+            }
+            Err(Failure::TooLarge(e)) => e,
+            // the rest would be refused just the same.
+            Err(Failure::MissingPermissions) => {
+                sent.denied = true;
+                break;
+            }
+            Err(failure) => {
+                sent.fail(failure);
+                continue;
+            }
+        };
+
+        if chunk.len() > 1 {
+            let second = chunk.split_off(chunk.len() / 2);
+            log::warn!(
+                "reply too large, splitting {} embeds into {} and {}.",
+                chunk.len() + second.len(),
+                chunk.len(),
+                second.len()
+            );
+
+            queue.push_front(second);
+            queue.push_front(chunk);
+        } else if chunk[0].1.thumbnail.is_some() {
+            log::warn!("reply too large, sending it without the thumbnail.");
+
+            chunk[0].1.thumbnail = None;
+            queue.push_front(chunk);
+        } else {
+            log::error!("could not send message, even the bare embed is too large: {e:#?}");
+        }
+    }
+
+    sent
+}
+
+/// Sends a plain-text reply, for channels the bot can't embed in.
+pub async fn send_text(
+    http: &Http,
+    channel_id: ChannelId,
+    reference: Option<&Message>,
+    content: String,
+) -> Sent {
+    let message = CreateMessage::new().content(content);
+
+    let message = match reference {
+        Some(reference) => message.reference_message(reference),
+        None => message,
+    };
+
+    Sent::single(deliver(http, channel_id, message).await)
+}
+
+/// Asks the channel whether the de-arrowed title is any good, for titles the community hasn't
+/// quite settled on yet.
+pub async fn send_trust_poll(
+    http: &Http,
+    channel_id: ChannelId,
+    reference: Option<&Message>,
+    title: &str,
+) -> Sent {
+    let poll = CreatePoll::new()
+        .question("Is this a good title?")
+        .answers(vec![
+            CreatePollAnswer::new().text("Yes").emoji("👍".to_string()),
+            CreatePollAnswer::new().text("No").emoji("👎".to_string()),
+        ])
+        .duration(std::time::Duration::from_secs(24 * 60 * 60));
+
+    let message = CreateMessage::new()
+        .content(format!("DeArrow isn't quite sure about \"{title}\" yet."))
+        .poll(poll);
+
+    let message = match reference {
+        Some(reference) => message.reference_message(reference),
+        None => message,
+    };
+
+    match deliver(http, channel_id, message).await {
+        Err(Failure::Other(e)) => {
+            log::warn!("could not send trust poll, polls may not be available here: {e:#?}");
+            Sent::default()
+        }
+        // not being allowed to poll doesn't mean we can't reply.
+        Err(Failure::MissingPermissions) => {
+            log::info!("no permission to send polls in {channel_id}.");
+            Sent::default()
+        }
+        result => Sent::single(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageFormat;
+
+    fn part(n: usize, thumbnail: bool) -> ReplyPart {
+        ReplyPart {
+            embed: CreateEmbed::new().title(format!("video {n}")),
+            thumbnail: thumbnail.then(|| Thumbnail {
+                bytes: vec![0xFF, 0xD8, 0xFF],
+                format: ImageFormat::Jpeg,
+            }),
+            alt_text: None,
+            buttons: Vec::new(),
+        }
+    }
+
+    /// One message as it reached Discord.
+    #[derive(Debug, PartialEq)]
+    struct Attempt {
+        titles: Vec<String>,
+        files: usize,
+        content: Option<String>,
+    }
+
+    impl Attempt {
+        fn of(message: &CreateMessage) -> Self {
+            let json = serde_json::to_value(message).unwrap();
+            let list = |key: &str| json[key].as_array().cloned().unwrap_or_default();
+
+            Attempt {
+                titles: list("embeds")
+                    .iter()
+                    .map(|x| x["title"].as_str().unwrap().to_string())
+                    .collect(),
+                files: list("attachments").len(),
+                content: json["content"].as_str().map(str::to_string),
+            }
+        }
+    }
+
+    /// Sends `parts` to a fake Discord, which refuses the messages `too_large` says are.
+    /// Returns every message tried, whether it went through, and what [`send_with`] made of it.
+    async fn send_to(
+        parts: Vec<ReplyPart>,
+        limit: EmbedLimit,
+        too_large: impl Fn(&Attempt) -> bool,
+    ) -> (Vec<(Attempt, bool)>, Sent) {
+        let mut attempts = Vec::new();
+
+        let sent = send_with(
+            |message| {
+                let attempt = Attempt::of(&message);
+                let refused = too_large(&attempt);
+                attempts.push((attempt, !refused));
+
+                async move {
+                    if refused {
+                        Err(Failure::TooLarge(serenity::Error::Other("too large")))
+                    } else {
+                        Ok(Message::default())
+                    }
+                }
+            },
+            None,
+            parts,
+            limit,
+        )
+        .await;
+
+        (attempts, sent)
+    }
+
+    /// The embed titles of the messages that went through.
+    fn delivered(attempts: &[(Attempt, bool)]) -> Vec<Vec<String>> {
+        attempts
+            .iter()
+            .filter(|(_, ok)| *ok)
+            .map(|(x, _)| x.titles.clone())
+            .collect()
+    }
+
+    const SPLIT: EmbedLimit = EmbedLimit {
+        max: MAX_EMBEDS,
+        overflow: Overflow::Split,
+    };
+
+    #[test]
+    fn points_back_at_the_link_only_in_reference_mode() {
+        let mut msg = Message::default();
+        msg.guild_id = Some(serenity::all::GuildId::new(1));
+
+        let dm = Message::default();
+
+        for (name, msg, mode, referenced) in [
+            ("reference", &msg, ReplyMode::Reference, true),
+            ("standalone", &msg, ReplyMode::Standalone, false),
+            ("reference in a dm", &dm, ReplyMode::Reference, false),
+            ("standalone in a dm", &dm, ReplyMode::Standalone, false),
+        ] {
+            let reference = reference(msg, mode);
+            assert_eq!(reference.is_some(), referenced, "{name}");
+
+            let json = serde_json::to_value(build_message(reference, &[(0, part(0, false))], None))
+                .unwrap();
+            assert_eq!(
+                json.get("message_reference").is_some_and(|x| !x.is_null()),
+                referenced,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn tells_what_discord_refused_for() {
+        let classify = |status, code| Failure::classify(status, code, serenity::Error::Other(""));
+
+        assert!(matches!(classify(413, 0), Failure::TooLarge(_)));
+        assert!(matches!(
+            classify(400, ENTITY_TOO_LARGE),
+            Failure::TooLarge(_)
+        ));
+        assert!(matches!(classify(429, 0), Failure::RateLimited(_)));
+        assert!(matches!(classify(403, 0), Failure::MissingPermissions));
+        assert!(matches!(
+            classify(400, MISSING_PERMISSIONS),
+            Failure::MissingPermissions
+        ));
+        assert!(matches!(classify(400, 50035), Failure::Other(_)));
+    }
+
+    #[tokio::test]
+    async fn splits_replies_past_the_embed_limit() {
+        for (max, sizes) in [
+            (MAX_EMBEDS, vec![10, 2]),
+            (3, vec![3, 3, 3, 3]),
+            // out of range limits end up in range.
+            (0, vec![1; 12]),
+            (20, vec![10, 2]),
+        ] {
+            let parts = (0..12).map(|n| part(n, false)).collect();
+            let limit = EmbedLimit {
+                max,
+                overflow: Overflow::Split,
+            };
+
+            let (attempts, sent) = send_to(parts, limit, |_| false).await;
+
+            let tried = attempts
+                .iter()
+                .map(|(x, _)| x.titles.len())
+                .collect::<Vec<_>>();
+            assert_eq!(tried, sizes, "max {max}");
+            assert!(
+                attempts.iter().all(|(x, _)| x.content.is_none()),
+                "max {max}"
+            );
+            assert!((0..12).all(|x| sent.delivered(x)), "max {max}");
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_embeds_past_the_limit_with_a_note() {
+        let parts = (0..7).map(|n| part(n, false)).collect();
+        let limit = EmbedLimit {
+            max: 3,
+            overflow: Overflow::Drop,
+        };
+
+        let (attempts, sent) = send_to(parts, limit, |_| false).await;
+
+        assert_eq!(
+            attempts,
+            [(
+                Attempt {
+                    titles: vec!["video 0".into(), "video 1".into(), "video 2".into()],
+                    files: 0,
+                    content: Some("+4 more".into()),
+                },
+                true
+            )]
+        );
+        assert!((0..3).all(|x| sent.delivered(x)));
+        assert!(!sent.delivered(3));
+    }
+
+    #[tokio::test]
+    async fn halves_replies_that_are_too_large_keeping_their_order() {
+        let parts = (0..4).map(|n| part(n, true)).collect();
+
+        // only one thumbnail fits in a message.
+        let (attempts, sent) = send_to(parts, SPLIT, |x| x.files > 1).await;
+
+        let tried = attempts.iter().map(|(x, _)| x.files).collect::<Vec<_>>();
+        assert_eq!(tried, [4, 2, 1, 1, 2, 1, 1]);
+        assert_eq!(
+            delivered(&attempts),
+            [["video 0"], ["video 1"], ["video 2"], ["video 3"]]
+        );
+        assert_eq!(sent.messages.len(), 4);
+        assert!((0..4).all(|x| sent.delivered(x)));
+    }
+
+    #[tokio::test]
+    async fn sends_a_part_too_large_on_its_own_without_its_thumbnail() {
+        let (attempts, sent) =
+            send_to(vec![part(0, true), part(1, false)], SPLIT, |x| x.files > 0).await;
+
+        let tried = attempts
+            .iter()
+            .map(|(x, ok)| (x.titles.len(), x.files, *ok))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tried,
+            [(2, 1, false), (1, 1, false), (1, 0, true), (1, 0, true)]
+        );
+        assert!(sent.delivered(0) && sent.delivered(1));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_bare_embed_that_is_too_large() {
+        let parts = vec![part(0, true), part(1, false)];
+        let (attempts, sent) = send_to(parts, SPLIT, |_| true).await;
+
+        // both halves, then the first once more without its thumbnail.
+        assert_eq!(attempts.len(), 4);
+        assert!(!sent.any());
+    }
+}
@@ -0,0 +1,290 @@
+//! Sharing the message processing fairly between guilds, so a flood of links in one of them
+//! doesn't hold up the others.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serenity::all::GuildId;
+use tokio::sync::oneshot;
+
+/// Whose turn a message is processed on. DMs share one queue.
+type Key = Option<GuildId>;
+
+#[derive(Default)]
+struct Queues {
+    running: usize,
+    running_per_guild: HashMap<Key, usize>,
+    waiting: HashMap<Key, VecDeque<oneshot::Sender<()>>>,
+    /// Guilds with messages waiting, in the order they get their next turn.
+    rotation: VecDeque<Key>,
+}
+
+impl Queues {
+    /// Drops the guild's waiters that gave up, and the guild from the rotation if none are left.
+    fn prune(&mut self, guild_id: Key) {
+        let Some(waiting) = self.waiting.get_mut(&guild_id) else {
+            return;
+        };
+
+        waiting.retain(|x| !x.is_closed());
+
+        if waiting.is_empty() {
+            self.waiting.remove(&guild_id);
+            self.rotation.retain(|x| *x != guild_id);
+        }
+    }
+}
+
+/// Lets at most `max` messages be processed at once, and at most `max_per_guild` of them from
+/// the same guild. Guilds with messages waiting take turns, one message each.
+pub struct FairGate {
+    queues: Mutex<Queues>,
+    max: usize,
+    max_per_guild: usize,
+}
+
+impl FairGate {
+    pub fn new(max: usize, max_per_guild: usize) -> Self {
+        Self {
+            queues: Mutex::new(Queues::default()),
+            max: max.max(1),
+            max_per_guild: max_per_guild.max(1),
+        }
+    }
+
+    /// Waits for the guild's turn. Processing may go on until the returned permit is dropped.
+    pub async fn acquire(self: &Arc<Self>, guild_id: Key) -> Permit {
+        let (tx, rx) = oneshot::channel();
+
+        if let Ok(mut queues) = self.queues.lock() {
+            queues.waiting.entry(guild_id).or_default().push_back(tx);
+
+            if !queues.rotation.contains(&guild_id) {
+                queues.rotation.push_back(guild_id);
+            }
+
+            self.dispatch(&mut queues);
+        }
+
+        let mut waiter = Waiter {
+            gate: self,
+            guild_id,
+            rx,
+            done: false,
+        };
+
+        // a poisoned lock never hands out turns, don't wait on it forever.
+        let _ = (&mut waiter.rx).await;
+        waiter.done = true;
+
+        Permit {
+            gate: self.clone(),
+            guild_id,
+        }
+    }
+
+    /// How many messages are being processed or waiting for their turn.
+    pub fn busy(&self) -> usize {
+        self.queues
+            .lock()
+            .map(|x| {
+                // waiters that gave up stay queued until their guild's turn comes around.
+                let waiting = x.waiting.values().flatten().filter(|x| !x.is_closed());
+                x.running + waiting.count()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Waits until no messages are being processed or waiting anymore.
+    pub async fn drained(&self) {
+        while self.busy() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Hands out turns for as long as there's room, going around the waiting guilds.
+    fn dispatch(&self, queues: &mut Queues) {
+        let mut passed = 0;
+
+        while queues.running < self.max && passed < queues.rotation.len() {
+            let Some(guild_id) = queues.rotation.pop_front() else {
+                break;
+            };
+
+            let running = queues
+                .running_per_guild
+                .get(&guild_id)
+                .copied()
+                .unwrap_or_default();
+
+            if running >= self.max_per_guild {
+                queues.rotation.push_back(guild_id);
+                passed += 1;
+                continue;
+            }
+
+            let waiting = queues.waiting.entry(guild_id).or_default();
+
+            // skip over waiters that gave up.
+            let mut granted = false;
+            while let Some(tx) = waiting.pop_front() {
+                if tx.send(()).is_ok() {
+                    granted = true;
+                    break;
+                }
+            }
+
+            if !waiting.is_empty() {
+                queues.rotation.push_back(guild_id);
+            } else {
+                queues.waiting.remove(&guild_id);
+            }
+
+            if granted {
+                queues.running += 1;
+                *queues.running_per_guild.entry(guild_id).or_default() += 1;
+                passed = 0;
+            }
+        }
+    }
+
+    fn release(&self, guild_id: Key) {
+        let Ok(mut queues) = self.queues.lock() else {
+            return;
+        };
+
+        queues.running = queues.running.saturating_sub(1);
+
+        if let Some(running) = queues.running_per_guild.get_mut(&guild_id) {
+            *running = running.saturating_sub(1);
+
+            if *running == 0 {
+                queues.running_per_guild.remove(&guild_id);
+            }
+        }
+
+        self.dispatch(&mut queues);
+    }
+}
+
+/// A turn that was asked for, and may have been granted by the time it's dropped.
+struct Waiter<'a> {
+    gate: &'a FairGate,
+    guild_id: Key,
+    rx: oneshot::Receiver<()>,
+    /// Set once the turn is the permit's to give back.
+    done: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        // no turn can come in anymore, and the sender shows as closed to the pruning below.
+        self.rx.close();
+
+        // given up on after the turn came, give it back.
+        if self.rx.try_recv().is_ok() {
+            self.gate.release(self.guild_id);
+            return;
+        }
+
+        // given up on before, don't leave it queued.
+        if let Ok(mut queues) = self.gate.queues.lock() {
+            queues.prune(self.guild_id);
+        }
+    }
+}
+
+pub struct Permit {
+    gate: Arc<FairGate>,
+    guild_id: Key,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.gate.release(self.guild_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guild(id: u64) -> Key {
+        Some(GuildId::new(id))
+    }
+
+    /// Queues a message from `guild_id`, which notes down `label` once it gets its turn.
+    async fn queue(
+        gate: &Arc<FairGate>,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+        guild_id: Key,
+        label: &'static str,
+    ) -> tokio::task::JoinHandle<()> {
+        let busy = gate.busy();
+        let (waiting, order) = (gate.clone(), order.clone());
+
+        let task = tokio::spawn(async move {
+            let _turn = waiting.acquire(guild_id).await;
+            order.lock().unwrap().push(label);
+        });
+
+        // make sure it's waiting before queueing the next one.
+        while gate.busy() == busy {
+            tokio::task::yield_now().await;
+        }
+
+        task
+    }
+
+    #[tokio::test]
+    async fn takes_turns_between_guilds() {
+        let gate = Arc::new(FairGate::new(1, 1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let first = gate.acquire(guild(1)).await;
+
+        let mut tasks = Vec::new();
+        for (guild_id, label) in [
+            (guild(1), "1a"),
+            (guild(1), "1b"),
+            (guild(1), "1c"),
+            (guild(2), "2a"),
+            (None, "dm"),
+        ] {
+            tasks.push(queue(&gate, &order, guild_id, label).await);
+        }
+
+        assert_eq!(gate.busy(), 6);
+        drop(first);
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), ["1a", "2a", "dm", "1b", "1c"]);
+        assert_eq!(gate.busy(), 0);
+    }
+
+    #[tokio::test]
+    async fn forgets_waiters_that_gave_up() {
+        let gate = Arc::new(FairGate::new(1, 1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let first = gate.acquire(guild(1)).await;
+        let task = queue(&gate, &order, guild(2), "2a").await;
+        assert_eq!(gate.busy(), 2);
+
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert_eq!(gate.busy(), 1);
+
+        drop(first);
+        assert_eq!(gate.busy(), 0);
+        assert!(order.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,668 @@
+//! Bookkeeping of what the bot already did, optionally persisted across restarts.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serenity::all::{ChannelId, MessageId, UserId};
+
+/// Entries that are only ever looked at for a limited time, so the in-memory maps get pruned
+/// once they grow past this.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Bookkeeping whose entries expire, so it can be swept by a [`Sweeper`].
+pub trait Sweep: Send + Sync {
+    /// Drops expired entries, returning how many were dropped.
+    fn sweep(&self) -> usize;
+}
+
+/// Periodically evicts expired entries from all registered maps, so they don't grow
+/// unbounded on long-running deployments.
+#[derive(Default)]
+pub struct Sweeper {
+    maps: Vec<(&'static str, Arc<dyn Sweep>)>,
+}
+
+impl Sweeper {
+    pub fn register(&mut self, name: &'static str, map: Arc<dyn Sweep>) {
+        self.maps.push((name, map));
+    }
+
+    pub fn spawn(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+
+            loop {
+                interval.tick().await;
+
+                for (name, map) in &self.maps {
+                    let evicted = map.sweep();
+
+                    if evicted > 0 {
+                        log::debug!("evicted {evicted} expired entries from {name}.");
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// On-disk store for state that should survive restarts and redeploys.
+pub struct Store {
+    db: sled::Db,
+    max_age: Duration,
+}
+
+impl Store {
+    pub fn open(path: &Path, max_age: Duration) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+            max_age,
+        })
+    }
+
+    /// Opens a tree of keys with the time they were recorded at.
+    pub fn timestamps(&self, name: &str) -> anyhow::Result<TimestampTree> {
+        self.timestamps_expiring(name, self.max_age)
+    }
+
+    /// [`Self::timestamps`], with entries expiring after `max_age` instead of the store's.
+    pub fn timestamps_expiring(
+        &self,
+        name: &str,
+        max_age: Duration,
+    ) -> anyhow::Result<TimestampTree> {
+        Ok(TimestampTree {
+            tree: self.db.open_tree(name)?,
+            max_age,
+        })
+    }
+}
+
+pub struct TimestampTree {
+    tree: sled::Tree,
+    max_age: Duration,
+}
+
+impl TimestampTree {
+    pub fn insert(&self, key: &str, at: SystemTime) {
+        let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        if let Err(e) = self.tree.insert(key, &secs.to_be_bytes()) {
+            log::error!("failed to persist {key}: {e:#?}");
+        }
+    }
+
+    /// Loads all entries younger than the store's max age, dropping the rest from disk.
+    pub fn load(&self) -> Vec<(String, SystemTime)> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+
+        for entry in self.tree.iter() {
+            let Ok((key, value)) = entry.map_err(|e| log::error!("failed to read store: {e:#?}"))
+            else {
+                continue;
+            };
+
+            let at = <[u8; 8]>::try_from(value.as_ref())
+                .map(|x| UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(x)));
+
+            match (std::str::from_utf8(&key), at) {
+                (Ok(name), Ok(at)) if !expired(now, at, self.max_age) => {
+                    entries.push((name.to_string(), at))
+                }
+                _ => {
+                    let _ = self.tree.remove(key);
+                }
+            }
+        }
+
+        entries
+    }
+
+    pub fn remove(&self, key: &str) {
+        if let Err(e) = self.tree.remove(key) {
+            log::error!("failed to drop {key} from the store: {e:#?}");
+        }
+    }
+
+    /// Drops entries older than the store's max age from disk.
+    pub fn prune(&self) {
+        let _ = self.load();
+    }
+}
+
+/// Keys that can go into a [`TimestampTree`].
+pub trait StoreKey: Sized {
+    fn to_store_key(&self) -> String;
+    fn from_store_key(key: &str) -> Option<Self>;
+}
+
+impl StoreKey for String {
+    fn to_store_key(&self) -> String {
+        self.clone()
+    }
+
+    fn from_store_key(key: &str) -> Option<Self> {
+        Some(key.to_string())
+    }
+}
+
+impl StoreKey for (ChannelId, String) {
+    fn to_store_key(&self) -> String {
+        format!("{}:{}", self.0, self.1)
+    }
+
+    fn from_store_key(key: &str) -> Option<Self> {
+        let (channel_id, rest) = key.split_once(':')?;
+        let channel_id = channel_id.parse().ok().filter(|x| *x != 0)?;
+
+        Some((ChannelId::new(channel_id), rest.to_string()))
+    }
+}
+
+impl StoreKey for (ChannelId, UserId) {
+    fn to_store_key(&self) -> String {
+        format!("{}:{}", self.0, self.1)
+    }
+
+    fn from_store_key(key: &str) -> Option<Self> {
+        let (channel_id, user_id) = key.split_once(':')?;
+        let channel_id = channel_id.parse().ok().filter(|x| *x != 0)?;
+        let user_id = user_id.parse().ok().filter(|x| *x != 0)?;
+
+        Some((ChannelId::new(channel_id), UserId::new(user_id)))
+    }
+}
+
+fn expired(now: SystemTime, at: SystemTime, max_age: Duration) -> bool {
+    now.duration_since(at).unwrap_or_default() > max_age
+}
+
+/// Which videos have already been replied to for which message, so a re-delivered message
+/// doesn't get a second reply.
+pub struct ReplyLog {
+    replies: Mutex<HashMap<(MessageId, String), SystemTime>>,
+    /// Replies currently being worked on, for messages that arrive through more than one event.
+    in_flight: Mutex<HashSet<(MessageId, String)>>,
+    max_age: Duration,
+    persisted: Option<TimestampTree>,
+}
+
+impl ReplyLog {
+    pub fn new(max_age: Duration, persisted: Option<TimestampTree>) -> Self {
+        let mut replies = HashMap::new();
+
+        if let Some(ref tree) = persisted {
+            for (key, at) in tree.load() {
+                let Some((msg_id, vid_id)) = key.split_once(':').and_then(|(msg_id, vid_id)| {
+                    Some((msg_id.parse().ok().filter(|x| *x != 0)?, vid_id))
+                }) else {
+                    continue;
+                };
+
+                replies.insert((MessageId::new(msg_id), vid_id.to_string()), at);
+            }
+
+            log::info!("loaded {} persisted reply records.", replies.len());
+        }
+
+        Self {
+            replies: Mutex::new(replies),
+            in_flight: Mutex::new(HashSet::new()),
+            max_age,
+            persisted,
+        }
+    }
+
+    pub fn contains(&self, msg_id: MessageId, vid_id: &str) -> bool {
+        let Ok(replies) = self.replies.lock() else {
+            return false;
+        };
+
+        replies
+            .get(&(msg_id, vid_id.to_string()))
+            .is_some_and(|at| !expired(SystemTime::now(), *at, self.max_age))
+    }
+
+    /// Marks a reply as being worked on until the returned claim is dropped. `None` if it was
+    /// already sent, or is being worked on elsewhere. With `again`, one that was already sent can
+    /// be claimed too.
+    pub fn claim(&self, msg_id: MessageId, vid_id: &str, again: bool) -> Option<ReplyClaim<'_>> {
+        if !again && self.contains(msg_id, vid_id) {
+            return None;
+        }
+
+        let key = (msg_id, vid_id.to_string());
+
+        if !self.in_flight.lock().ok()?.insert(key.clone()) {
+            return None;
+        }
+
+        Some(ReplyClaim { log: self, key })
+    }
+
+    pub fn insert(&self, msg_id: MessageId, vid_id: &str) {
+        let now = SystemTime::now();
+
+        if let Ok(mut replies) = self.replies.lock() {
+            if replies.len() >= PRUNE_THRESHOLD {
+                replies.retain(|_, at| !expired(now, *at, self.max_age));
+
+                if let Some(ref tree) = self.persisted {
+                    tree.prune();
+                }
+            }
+
+            replies.insert((msg_id, vid_id.to_string()), now);
+        }
+
+        if let Some(ref tree) = self.persisted {
+            tree.insert(&format!("{msg_id}:{vid_id}"), now);
+        }
+    }
+}
+
+pub struct ReplyClaim<'a> {
+    log: &'a ReplyLog,
+    key: (MessageId, String),
+}
+
+impl Drop for ReplyClaim<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.log.in_flight.lock() {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+impl Sweep for ReplyLog {
+    fn sweep(&self) -> usize {
+        let now = SystemTime::now();
+
+        let Ok(mut replies) = self.replies.lock() else {
+            return 0;
+        };
+
+        let before = replies.len();
+        replies.retain(|_, at| !expired(now, *at, self.max_age));
+
+        if let Some(ref tree) = self.persisted {
+            tree.prune();
+        }
+
+        before - replies.len()
+    }
+}
+
+/// How a key is named in a [`TimestampTree`].
+type KeyName<K> = fn(&K) -> String;
+
+/// Remembers when something was last done for a key, to hold off on doing it again within a
+/// window. Holds at most `capacity` keys, evicting the oldest when full.
+pub struct Cooldowns<K> {
+    last: Mutex<HashMap<K, Instant>>,
+    window: Duration,
+    capacity: usize,
+    /// Where the cooldowns are kept across restarts, along with how keys are named in there.
+    persisted: Option<(TimestampTree, KeyName<K>)>,
+}
+
+impl<K: Eq + Hash + Clone> Cooldowns<K> {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            last: Mutex::new(HashMap::new()),
+            window,
+            capacity,
+            persisted: None,
+        }
+    }
+
+    /// [`Self::new`], kept in `tree` across restarts, and starting out with the cooldowns
+    /// still running in there.
+    pub fn persisted(window: Duration, capacity: usize, tree: TimestampTree) -> Self
+    where
+        K: StoreKey,
+    {
+        let now = SystemTime::now();
+        let mut last = HashMap::new();
+
+        for (key, at) in tree.load() {
+            let elapsed = now.duration_since(at).unwrap_or_default();
+
+            // right after a reboot, an instant that far back may not exist. better to let the
+            // cooldown go than to start it over.
+            let parsed = K::from_store_key(&key).zip(Instant::now().checked_sub(elapsed));
+
+            match parsed {
+                Some((parsed, at)) if elapsed < window && last.len() < capacity => {
+                    last.insert(parsed, at);
+                }
+                _ => tree.remove(&key),
+            }
+        }
+
+        log::info!("loaded {} persisted cooldowns.", last.len());
+
+        Self {
+            last: Mutex::new(last),
+            window,
+            capacity,
+            persisted: Some((tree, K::to_store_key)),
+        }
+    }
+
+    /// Drops `keys` from the persisted cooldowns, if they're persisted.
+    fn unpersist<'a>(&self, keys: impl IntoIterator<Item = &'a K>)
+    where
+        K: 'a,
+    {
+        if let Some((ref tree, name)) = self.persisted {
+            for key in keys {
+                tree.remove(&name(key));
+            }
+        }
+    }
+
+    pub fn is_cooling_down(&self, key: &K) -> bool {
+        let Ok(last) = self.last.lock() else {
+            return false;
+        };
+
+        last.get(key).is_some_and(|at| at.elapsed() < self.window)
+    }
+
+    pub fn start(&self, key: K) {
+        let Ok(mut last) = self.last.lock() else {
+            return;
+        };
+
+        if last.len() >= self.capacity && !last.contains_key(&key) {
+            self.drop_where(&mut last, |_, at| at.elapsed() >= self.window);
+
+            if last.len() >= self.capacity
+                && let Some(oldest) = last
+                    .iter()
+                    .min_by_key(|(_, at)| **at)
+                    .map(|(k, _)| k.clone())
+            {
+                last.remove(&oldest);
+                self.unpersist([&oldest]);
+            }
+        }
+
+        if let Some((ref tree, name)) = self.persisted {
+            tree.insert(&name(&key), SystemTime::now());
+        }
+
+        last.insert(key, Instant::now());
+    }
+
+    /// Drops the keys `f` matches, returning how many were dropped.
+    pub fn forget(&self, f: impl Fn(&K) -> bool) -> usize {
+        let Ok(mut last) = self.last.lock() else {
+            return 0;
+        };
+
+        self.drop_where(&mut last, |key, _| f(key))
+    }
+
+    /// Drops the entries `f` matches from `last` and the persisted cooldowns, returning how
+    /// many were dropped.
+    fn drop_where(
+        &self,
+        last: &mut HashMap<K, Instant>,
+        f: impl Fn(&K, &Instant) -> bool,
+    ) -> usize {
+        let dropped = last
+            .iter()
+            .filter(|(key, at)| f(key, at))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in &dropped {
+            last.remove(key);
+        }
+
+        self.unpersist(&dropped);
+
+        dropped.len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send> Sweep for Cooldowns<K> {
+    fn sweep(&self) -> usize {
+        let Ok(mut last) = self.last.lock() else {
+            return 0;
+        };
+
+        self.drop_where(&mut last, |_, at| at.elapsed() >= self.window)
+    }
+}
+
+/// Allows at most `limit` uses per key within any sliding `window`. Holds at most `capacity`
+/// keys, evicting the least recently used when full.
+pub struct RateLimiter<K> {
+    uses: Mutex<HashMap<K, VecDeque<Instant>>>,
+    limit: usize,
+    window: Duration,
+    capacity: usize,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    pub fn new(limit: usize, window: Duration, capacity: usize) -> Self {
+        Self {
+            uses: Mutex::new(HashMap::new()),
+            limit,
+            window,
+            capacity,
+        }
+    }
+
+    /// Records a use for `key`, unless it already used up its limit.
+    pub fn try_acquire(&self, key: K) -> bool {
+        let Ok(mut uses) = self.uses.lock() else {
+            return true;
+        };
+
+        if uses.len() >= self.capacity && !uses.contains_key(&key) {
+            uses.retain(|_, x| x.back().is_some_and(|at| at.elapsed() < self.window));
+
+            if uses.len() >= self.capacity
+                && let Some(oldest) = uses
+                    .iter()
+                    .min_by_key(|(_, x)| x.back().copied())
+                    .map(|(k, _)| k.clone())
+            {
+                uses.remove(&oldest);
+            }
+        }
+
+        let recent = uses.entry(key).or_default();
+
+        while recent.front().is_some_and(|at| at.elapsed() >= self.window) {
+            recent.pop_front();
+        }
+
+        if recent.len() >= self.limit {
+            return false;
+        }
+
+        recent.push_back(Instant::now());
+        true
+    }
+
+    /// Drops the keys `f` matches, returning how many were dropped.
+    pub fn forget(&self, f: impl Fn(&K) -> bool) -> usize {
+        let Ok(mut uses) = self.uses.lock() else {
+            return 0;
+        };
+
+        let before = uses.len();
+        uses.retain(|key, _| !f(key));
+
+        before - uses.len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send> Sweep for RateLimiter<K> {
+    fn sweep(&self) -> usize {
+        let Ok(mut uses) = self.uses.lock() else {
+            return 0;
+        };
+
+        let before = uses.len();
+        uses.retain(|_, x| x.back().is_some_and(|at| at.elapsed() < self.window));
+
+        before - uses.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Keeps [`Instant::now`] from handing out the same instant twice, so which key is the
+    /// oldest is settled.
+    fn tick() {
+        std::thread::sleep(Duration::from_millis(2));
+    }
+
+    #[test]
+    fn cools_down_for_the_window() {
+        let cooldowns = Cooldowns::new(HOUR, 16);
+        cooldowns.start("a");
+
+        assert!(cooldowns.is_cooling_down(&"a"));
+        assert!(!cooldowns.is_cooling_down(&"b"));
+
+        let expired = Cooldowns::new(Duration::ZERO, 16);
+        expired.start("a");
+
+        assert!(!expired.is_cooling_down(&"a"));
+        assert_eq!(expired.sweep(), 1);
+    }
+
+    #[test]
+    fn evicts_the_oldest_cooldown_when_full() {
+        let cooldowns = Cooldowns::new(HOUR, 2);
+        cooldowns.start("a");
+        tick();
+        cooldowns.start("b");
+        tick();
+        // restarting a key already there doesn't evict anything.
+        cooldowns.start("a");
+        tick();
+        cooldowns.start("c");
+
+        assert!(cooldowns.is_cooling_down(&"a"));
+        assert!(!cooldowns.is_cooling_down(&"b"));
+        assert!(cooldowns.is_cooling_down(&"c"));
+    }
+
+    #[test]
+    fn forgets_the_cooldowns_asked_to() {
+        let cooldowns = Cooldowns::new(HOUR, 16);
+        for key in [1, 2, 3, 4] {
+            cooldowns.start(key);
+        }
+
+        assert_eq!(cooldowns.forget(|x| x % 2 == 0), 2);
+        assert!(cooldowns.is_cooling_down(&1) && !cooldowns.is_cooling_down(&2));
+    }
+
+    #[test]
+    fn rate_limits_each_key_on_its_own() {
+        let limiter = RateLimiter::new(2, HOUR, 16);
+
+        assert!(limiter.try_acquire("a"));
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.try_acquire("a"));
+        assert!(limiter.try_acquire("b"));
+
+        // a refused use doesn't count against the limit once the window moves on.
+        let expired = RateLimiter::new(1, Duration::ZERO, 16);
+        assert!(expired.try_acquire("a"));
+        assert!(expired.try_acquire("a"));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_limit_when_full() {
+        let limiter = RateLimiter::new(1, HOUR, 2);
+        limiter.try_acquire("a");
+        tick();
+        limiter.try_acquire("b");
+        tick();
+        limiter.try_acquire("c");
+
+        // a was evicted, so it starts over.
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.try_acquire("c"));
+    }
+
+    #[test]
+    fn forgets_the_limits_asked_to() {
+        let limiter = RateLimiter::new(1, HOUR, 16);
+        limiter.try_acquire(1);
+        limiter.try_acquire(2);
+
+        assert_eq!(limiter.forget(|x| *x == 1), 1);
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.try_acquire(2));
+    }
+
+    fn store() -> Store {
+        Store {
+            db: sled::Config::new().temporary(true).open().unwrap(),
+            max_age: HOUR,
+        }
+    }
+
+    #[test]
+    fn names_keys_in_the_store_reversibly() {
+        let channel = ChannelId::new(1);
+
+        for key in [
+            (channel, "dQw4w9WgXcQ".to_string()),
+            // video ids may well have the separator in them.
+            (channel, "a:b".to_string()),
+        ] {
+            assert_eq!(StoreKey::from_store_key(&key.to_store_key()), Some(key));
+        }
+
+        let key = (channel, UserId::new(2));
+        assert_eq!(StoreKey::from_store_key(&key.to_store_key()), Some(key));
+
+        assert_eq!(<(ChannelId, UserId)>::from_store_key("0:2"), None);
+        assert_eq!(<(ChannelId, UserId)>::from_store_key("1:x"), None);
+        assert_eq!(<(ChannelId, String)>::from_store_key("nope"), None);
+    }
+
+    #[test]
+    fn picks_up_persisted_cooldowns_after_a_restart() {
+        let store = store();
+        let open =
+            || Cooldowns::persisted(HOUR, 16, store.timestamps_expiring("test", HOUR).unwrap());
+
+        let before: Cooldowns<String> = open();
+        for key in ["a", "b", "c"] {
+            before.start(key.to_string());
+        }
+        before.forget(|x| x == "b");
+
+        let after = open();
+        assert!(after.is_cooling_down(&"a".to_string()));
+        assert!(!after.is_cooling_down(&"b".to_string()));
+        assert!(after.is_cooling_down(&"c".to_string()));
+
+        // expired ones are gone from the store too.
+        let expired: Cooldowns<String> =
+            Cooldowns::persisted(Duration::ZERO, 16, store.timestamps("test").unwrap());
+        assert!(!expired.is_cooling_down(&"a".to_string()));
+        assert!(store.timestamps("test").unwrap().load().is_empty());
+    }
+}
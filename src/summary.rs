@@ -0,0 +1,108 @@
+//! Periodic summaries of what the bot did, posted to a channel.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serenity::all::{ChannelId, CreateMessage, Http};
+
+struct Counts {
+    since: SystemTime,
+    videos: usize,
+    channels: HashSet<ChannelId>,
+}
+
+impl Counts {
+    fn new() -> Self {
+        Self {
+            since: SystemTime::now(),
+            videos: 0,
+            channels: HashSet::new(),
+        }
+    }
+}
+
+/// Counts replies since the last summary. Kept in memory only, so after a restart the next
+/// summary covers the time since the restart.
+pub struct Activity {
+    counts: Mutex<Counts>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            counts: Mutex::new(Counts::new()),
+        }
+    }
+
+    pub fn record(&self, channel_id: ChannelId) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.videos += 1;
+            counts.channels.insert(channel_id);
+        }
+    }
+
+    /// Takes the counts so far and starts counting anew. `None` if nothing happened, in which
+    /// case the counts keep rolling.
+    fn take(&self) -> Option<Counts> {
+        let mut counts = self.counts.lock().ok()?;
+
+        if counts.videos == 0 {
+            return None;
+        }
+
+        Some(std::mem::replace(&mut *counts, Counts::new()))
+    }
+
+    /// Puts back counts whose summary couldn't be posted, so the next one includes them.
+    fn restore(&self, taken: Counts) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.since = taken.since;
+            counts.videos += taken.videos;
+            counts.channels.extend(taken.channels);
+        }
+    }
+}
+
+pub fn spawn_poster(
+    activity: Arc<Activity>,
+    http: Arc<Http>,
+    channel_id: ChannelId,
+    every: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        // the first tick completes immediately.
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let Some(counts) = activity.take() else {
+                continue;
+            };
+
+            let since = counts
+                .since
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            let content = format!(
+                "Since <t:{since}:f>, I de-clickbaited {} video{} across {} channel{}.",
+                counts.videos,
+                if counts.videos == 1 { "" } else { "s" },
+                counts.channels.len(),
+                if counts.channels.len() == 1 { "" } else { "s" },
+            );
+
+            if let Err(e) = channel_id
+                .send_message(&http, CreateMessage::new().content(content))
+                .await
+            {
+                log::warn!("could not post the activity summary to {channel_id}: {e:#?}");
+                activity.restore(counts);
+            }
+        }
+    });
+}
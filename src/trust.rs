@@ -0,0 +1,80 @@
+//! How much a crowd-sourced submission can be trusted, as communicated to viewers.
+
+/// Vote boundaries between the trust tiers. Locked submissions are always [`TrustTier::Verified`].
+#[derive(Clone, Copy, Debug)]
+pub struct TrustTiers {
+    pub trusted_votes: isize,
+    pub community_votes: isize,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum TrustTier {
+    Unverified,
+    Community,
+    Trusted,
+    Verified,
+}
+
+impl TrustTiers {
+    pub fn tier(&self, votes: isize, locked: bool) -> TrustTier {
+        if locked {
+            TrustTier::Verified
+        } else if votes >= self.trusted_votes {
+            TrustTier::Trusted
+        } else if votes >= self.community_votes {
+            TrustTier::Community
+        } else {
+            TrustTier::Unverified
+        }
+    }
+}
+
+impl std::fmt::Display for TrustTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TrustTier::Verified => "✅ Verified",
+            TrustTier::Trusted => "🛡️ Trusted",
+            TrustTier::Community => "👥 Community",
+            TrustTier::Unverified => "❔ Unverified",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_votes_and_locks_to_tiers() {
+        let tiers = TrustTiers {
+            trusted_votes: 5,
+            community_votes: 1,
+        };
+
+        for (votes, locked, expected) in [
+            (-3, false, TrustTier::Unverified),
+            (0, false, TrustTier::Unverified),
+            (1, false, TrustTier::Community),
+            (4, false, TrustTier::Community),
+            (5, false, TrustTier::Trusted),
+            (100, false, TrustTier::Trusted),
+            // locked is verified, whatever the votes.
+            (-3, true, TrustTier::Verified),
+            (0, true, TrustTier::Verified),
+            (100, true, TrustTier::Verified),
+        ] {
+            assert_eq!(tiers.tier(votes, locked), expected, "{votes} {locked}");
+        }
+    }
+
+    #[test]
+    fn trusted_wins_over_community_when_the_boundaries_overlap() {
+        let tiers = TrustTiers {
+            trusted_votes: 1,
+            community_votes: 3,
+        };
+
+        assert_eq!(tiers.tier(0, false), TrustTier::Unverified);
+        assert_eq!(tiers.tier(1, false), TrustTier::Trusted);
+    }
+}
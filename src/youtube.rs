@@ -0,0 +1,510 @@
+//! Talking to YouTube itself, for what DeArrow can't tell us.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use regex::Regex;
+
+/// Clip pages are large, but the parent video ID shows up well before this.
+const MAX_CLIP_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+static CLIP_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"youtube\.com\/clip\/([a-zA-Z0-9_-]{1,64})"#).expect("failed to compile regex")
+});
+
+static CLIP_VIDEO_ID_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#""videoId":"([a-zA-Z0-9_-]{11})""#).expect("failed to compile regex")
+});
+
+fn is_youtube_host(url: &reqwest::Url) -> bool {
+    matches!(
+        url.host_str(),
+        Some("youtube.com" | "www.youtube.com" | "m.youtube.com")
+    )
+}
+
+/// The leading video ID in `s`, if it starts with one.
+fn leading_video_id(s: &str) -> Option<&str> {
+    let len = s
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-')
+        .unwrap_or(s.len());
+
+    (len == 11).then(|| &s[..len])
+}
+
+/// The first path segments of YouTube links that point at a single video, followed by its ID.
+/// Anything else (search results, feeds, channels, playlists, ...) is declined before we go
+/// looking for an ID, since their query params can contain 11 character tokens too.
+const VIDEO_PATHS: &[&str] = &["watch", "shorts", "embed", "v", "e", "live"];
+
+/// Maps all the YouTube host and path variants of a video link (mobile, music, nocookie,
+/// shorts, embeds, youtu.be, consent redirects, ...) to `https://www.youtube.com/watch?v=<id>`.
+/// `None` if `url` isn't a link to a video.
+pub fn normalize_youtube_url(url: &str) -> Option<String> {
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        // people often leave out the scheme.
+        Err(_) => reqwest::Url::parse(&format!("https://{url}")).ok()?,
+    };
+
+    let query = |key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+    };
+
+    let host = url.host_str()?.to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let mut segments = url.path_segments()?.filter(|x| !x.is_empty());
+
+    let id = match host {
+        "youtu.be" => leading_video_id(segments.next()?)?.to_string(),
+        // the link the consent page sends you on to once you're done with it.
+        "consent.youtube.com" => return normalize_youtube_url(&query("continue")?),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            // legacy links and some apps' deep links keep the path in the fragment, as in
+            // `/#/watch?v=...` or `/#!/watch?v=...`.
+            if segments.clone().next().is_none()
+                && let Some(path) = url
+                    .fragment()
+                    .and_then(|x| x.trim_start_matches('!').strip_prefix('/'))
+            {
+                return normalize_youtube_url(&format!("https://www.youtube.com/{path}"));
+            }
+
+            match segments.next()? {
+                // shared links of the form `/attribution_link?u=/watch?v=...`.
+                "attribution_link" => {
+                    return normalize_youtube_url(&format!(
+                        "https://www.youtube.com{}",
+                        query("u")?
+                    ));
+                }
+                path if !VIDEO_PATHS.contains(&path) => {
+                    log::debug!("ignoring non-video youtube link {url}.");
+                    return None;
+                }
+                // some clients put the id in the fragment, as in `/watch#v=...`.
+                "watch" => match query("v").or_else(|| fragment_v(&url)) {
+                    Some(v) => leading_video_id(&v)?.to_string(),
+                    None => leading_video_id(segments.next()?)?.to_string(),
+                },
+                // embedded playlists, which happen to be 11 characters long.
+                "embed" if segments.clone().next() == Some("videoseries") => return None,
+                _ => leading_video_id(segments.next()?)?.to_string(),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(format!("https://www.youtube.com/watch?v={id}"))
+}
+
+/// The `v` parameter in the fragment of `url`, if it has one like a query.
+fn fragment_v(url: &reqwest::Url) -> Option<String> {
+    url.fragment()?
+        .split('&')
+        .find_map(|x| x.strip_prefix("v="))
+        .map(str::to_string)
+}
+
+/// YouTube's own thumbnail for `vid_id`, in the highest resolution it has.
+pub async fn thumbnail(client: &reqwest::Client, vid_id: &str) -> anyhow::Result<crate::Thumbnail> {
+    let url = |size: &str| format!("https://i.ytimg.com/vi/{vid_id}/{size}.jpg");
+
+    let res = client.get(url("maxresdefault")).send().await?;
+
+    // videos that weren't uploaded in HD don't have one.
+    let res = match res.status() {
+        reqwest::StatusCode::NOT_FOUND => client.get(url("hqdefault")).send().await?,
+        _ => res,
+    };
+
+    Ok(crate::read_thumbnail(res.error_for_status()?).await?)
+}
+
+/// Finds the ID of the first YouTube video linked in `content`.
+pub fn find_video_id(content: &str) -> Option<String> {
+    find_video_ids(content).into_iter().next()
+}
+
+/// A video linked in a message, and where the link asks it to start playing.
+#[derive(Clone, Debug)]
+pub struct VideoLink {
+    pub id: String,
+    /// In whole seconds, from a `t=` or `start=` param.
+    pub start: Option<u32>,
+}
+
+impl VideoLink {
+    /// The video on YouTube, starting where the link did.
+    pub fn watch_url(&self) -> String {
+        match self.start {
+            Some(start) => format!("https://www.youtube.com/watch?v={}&t={start}s", self.id),
+            None => format!("https://www.youtube.com/watch?v={}", self.id),
+        }
+    }
+}
+
+/// Formats `secs` the way YouTube shows a position in a video, as in `1:30` or `1:02:03`.
+pub fn format_timestamp(secs: u32) -> String {
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{hours}:{mins:02}:{secs:02}")
+    } else {
+        format!("{mins}:{secs:02}")
+    }
+}
+
+/// Formats a video's length in seconds, as in `0:45`, `4:05` or `1:02:03`.
+pub fn format_duration(secs: f32) -> String {
+    format_timestamp(secs.round() as u32)
+}
+
+/// Where the video linked by `url` starts playing, from its `t` or `start` query param, or a
+/// `#t=` fragment. All the forms [`parse_timestamp`] knows are accepted.
+fn start_time(url: &str) -> Option<u32> {
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => reqwest::Url::parse(&format!("https://{url}")).ok()?,
+    };
+
+    let from_query = url
+        .query_pairs()
+        .find(|(k, _)| k == "t" || k == "start")
+        .map(|(_, v)| v.into_owned());
+    let from_fragment = url
+        .fragment()
+        .and_then(|x| x.split('&').find_map(|x| x.strip_prefix("t=")))
+        .map(str::to_string);
+
+    let secs = parse_timestamp(&from_query.or(from_fragment)?)?;
+
+    // 0 is where the video starts anyway.
+    (secs >= 1.0).then_some(secs as u32)
+}
+
+/// Finds the IDs of all YouTube videos linked in `content`, in order and without repeats.
+pub fn find_video_ids(content: &str) -> Vec<String> {
+    find_links(content).into_iter().map(|x| x.id).collect()
+}
+
+/// Finds all YouTube videos linked in `content`, in order and without repeats. A video linked
+/// more than once starts where its first link does.
+#[tracing::instrument(name = "extract", skip_all)]
+pub fn find_links(content: &str) -> Vec<VideoLink> {
+    let mut links = Vec::<VideoLink>::new();
+
+    for (token, url) in content
+        .split(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '(' | ')' | '|' | '"'))
+        .filter(|x| x.contains("youtu"))
+        .filter_map(|x| Some((x, normalize_youtube_url(x)?)))
+    {
+        let Some((_, id)) = url.split_once("?v=") else {
+            continue;
+        };
+
+        if !links.iter().any(|x| x.id == id) {
+            links.push(VideoLink {
+                id: id.to_string(),
+                start: start_time(token),
+            });
+        }
+    }
+
+    links
+}
+
+/// Finds the ID of a YouTube clip link in `content`.
+pub fn find_clip(content: &str) -> Option<&str> {
+    CLIP_REGEX
+        .captures(content)
+        .and_then(|cap| cap.get(1))
+        .map(|x| x.as_str())
+}
+
+/// Resolves YouTube clips to the video they are cut from.
+pub struct ClipResolver {
+    client: reqwest::Client,
+}
+
+impl ClipResolver {
+    pub fn new() -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= 5 {
+                    attempt.error("too many redirects")
+                } else if is_youtube_host(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    // e.g. the consent page, which doesn't contain the video.
+                    attempt.stop()
+                }
+            }))
+            .build()?;
+
+        Ok(Self { client })
+    }
+
+    /// Fetches the clip's page and pulls the parent video's ID out of it.
+    #[tracing::instrument(skip(self))]
+    pub async fn resolve(&self, clip_id: &str) -> anyhow::Result<String> {
+        let mut res = self
+            .client
+            .get(format!("https://www.youtube.com/clip/{clip_id}"))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        if !is_youtube_host(res.url()) {
+            anyhow::bail!("clip redirected away from youtube to {}", res.url());
+        }
+
+        let mut page = Vec::new();
+
+        while let Some(chunk) = res.chunk().await? {
+            // only rescan what's new, plus enough overlap for a match split across chunks.
+            let start = page.len().saturating_sub(32);
+            page.extend_from_slice(&chunk);
+
+            if let Some(cap) =
+                CLIP_VIDEO_ID_REGEX.captures(&String::from_utf8_lossy(&page[start..]))
+            {
+                return Ok(cap[1].to_string());
+            }
+
+            if page.len() > MAX_CLIP_PAGE_BYTES {
+                break;
+            }
+        }
+
+        anyhow::bail!("clip page does not contain a video id")
+    }
+}
+
+/// Parses a timestamp like `90`, `1:30`, `1:02:03` or `1h2m3s` into seconds.
+pub fn parse_timestamp(s: &str) -> Option<f32> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return None;
+    }
+
+    if let Ok(secs) = s.parse::<f32>() {
+        return (secs.is_finite() && secs >= 0.0).then_some(secs);
+    }
+
+    if s.contains(':') {
+        let mut secs = 0.0;
+
+        for part in s.split(':') {
+            let part = part
+                .parse::<f32>()
+                .ok()
+                .filter(|x| x.is_finite() && *x >= 0.0)?;
+            secs = secs * 60.0 + part;
+        }
+
+        return Some(secs);
+    }
+
+    let mut secs = 0.0;
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        let unit_at = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let (value, unit) = rest.split_at(unit_at);
+        let value = value.parse::<f32>().ok()?;
+
+        secs += value
+            * match unit.chars().next()? {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => return None,
+            };
+
+        rest = &unit[1..];
+    }
+
+    Some(secs)
+}
+
+#[derive(serde::Deserialize)]
+pub struct OEmbed {
+    pub title: String,
+    /// The uploading channel's name.
+    pub author_name: String,
+    pub author_url: String,
+}
+
+/// Whether the video is still up on YouTube, going by whether oEmbed knows it. Removed and
+/// private videos are answered with a 404 or 401 respectively.
+pub async fn exists(client: &reqwest::Client, vid_id: &str) -> anyhow::Result<bool> {
+    let res = client
+        .get(format!(
+            "https://www.youtube.com/oembed?format=json&url=https://www.youtube.com/watch?v={vid_id}"
+        ))
+        .send()
+        .await?;
+
+    match res.status().as_u16() {
+        401 | 403 | 404 => Ok(false),
+        _ => res.error_for_status().map(|_| true).map_err(Into::into),
+    }
+}
+
+/// Looks up the video's original title and uploader through YouTube's oEmbed endpoint.
+pub async fn oembed(client: &reqwest::Client, vid_id: &str) -> anyhow::Result<OEmbed> {
+    let res: OEmbed = client
+        .get(format!(
+            "https://www.youtube.com/oembed?format=json&url=https://www.youtube.com/watch?v={vid_id}"
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_every_variant_to_a_watch_link() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/shorts/dQw4w9WgXcQ?feature=share",
+            "https://www.youtube.com/live/dQw4w9WgXcQ?si=abcdefghijklmnop",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ?start=42",
+            "https://youtu.be/dQw4w9WgXcQ?t=42",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ&pp=ygUJcmljayByb2xs",
+            "music.youtube.com/watch?v=dQw4w9WgXcQ",
+        ] {
+            assert_eq!(
+                normalize_youtube_url(url).as_deref(),
+                Some("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+                "{url}"
+            );
+            assert_eq!(
+                find_video_ids(&format!("look: {url}!")),
+                ["dQw4w9WgXcQ"],
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_id_in_every_link_form() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?feature=share&v=dQw4w9WgXcQ&t=42",
+            "youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&list=RDAMVM",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=abcdefghijklmnop",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+            "https://www.youtube.com/live/dQw4w9WgXcQ",
+            "https://www.youtube.com/v/dQw4w9WgXcQ",
+            "https://www.youtube.com/#/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/#!/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch#v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch#t=1m&v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=1m",
+            "https://youtu.be/dQw4w9WgXcQ#t=1m",
+            "https://www.youtube.com/attribution_link?u=/watch%3Fv%3DdQw4w9WgXcQ",
+            "https://consent.youtube.com/m?continue=https%3A%2F%2Fwww.youtube.com%2Fwatch%3Fv%3DdQw4w9WgXcQ",
+        ] {
+            assert_eq!(find_video_ids(url), ["dQw4w9WgXcQ"], "{url}");
+        }
+    }
+
+    #[test]
+    fn finds_links_among_text_in_order_without_repeats() {
+        let content = "look <https://youtu.be/dQw4w9WgXcQ> and (youtube.com/shorts/jNQXAC9IVRw), \
+            again https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+        assert_eq!(find_video_ids(content), ["dQw4w9WgXcQ", "jNQXAC9IVRw"]);
+    }
+
+    #[test]
+    fn rejects_links_that_arent_to_a_video() {
+        for url in [
+            "https://vimeo.com/dQw4w9WgXcQ",
+            "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtu.be.example.com/dQw4w9WgXcQ",
+            "https://www.youtube.com/results?search_query=dQw4w9WgXcQ",
+            "https://www.youtube.com/playlist?list=dQw4w9WgXcQ",
+            "https://www.youtube.com/@dQw4w9WgXcQ",
+            "https://www.youtube.com/embed/videoseries?list=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=short",
+            "https://youtu.be/dQw4w9WgXcQtoolong",
+            "no links here",
+        ] {
+            assert!(find_video_ids(url).is_empty(), "{url}");
+        }
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(0.4), "0:00");
+        assert_eq!(format_duration(45.0), "0:45");
+        assert_eq!(format_duration(245.6), "4:06");
+        assert_eq!(format_duration(3723.0), "1:02:03");
+        assert_eq!(format_duration(36000.0), "10:00:00");
+    }
+
+    #[test]
+    fn normalizes_every_start_time_to_seconds() {
+        for (url, start) in [
+            ("https://youtu.be/dQw4w9WgXcQ?t=1m30s", Some(90)),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=90", Some(90)),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=90s",
+                Some(90),
+            ),
+            (
+                "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ?start=90",
+                Some(90),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=1:30",
+                Some(90),
+            ),
+            ("youtu.be/dQw4w9WgXcQ?t=1m30s", Some(90)),
+            ("https://youtu.be/dQw4w9WgXcQ?t=abc", None),
+            ("https://youtu.be/dQw4w9WgXcQ?t=0", None),
+            ("https://youtu.be/dQw4w9WgXcQ", None),
+        ] {
+            assert_eq!(start_time(url), start, "{url}");
+        }
+
+        for (timestamp, secs) in [
+            ("90", Some(90.0)),
+            ("1:30", Some(90.0)),
+            ("0:01:30", Some(90.0)),
+            ("1m30s", Some(90.0)),
+            ("1h2m3s", Some(3723.0)),
+            (" 90 ", Some(90.0)),
+            ("abc", None),
+            ("1:xx", None),
+            ("-5", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_timestamp(timestamp), secs, "{timestamp:?}");
+        }
+
+        for (secs, formatted) in [(0, "0:00"), (90, "1:30"), (3723, "1:02:03")] {
+            assert_eq!(format_timestamp(secs), formatted);
+        }
+    }
+}